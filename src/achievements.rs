@@ -0,0 +1,283 @@
+//! Achievements and the local profile they are saved to across runs.
+//!
+//! The profile keeps lifetime totals alongside the achievements unlocked so far.
+//! Totals are counted from the GameEvents of each turn, and achievements are
+//! checked against them and the GameStats of the current run. The game saves the
+//! profile next to its executable at the end of every turn that changed it, and
+//! when a run ends. Replays count towards nothing.
+
+use crate::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the file the profile is saved to.
+const PROFILE_FILE: &str = "profile.txt";
+
+/// Where the profile is saved, next to the executable so it does not depend on
+/// the directory the game is started from.
+pub fn profile_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(PROFILE_FILE)))
+        .unwrap_or_else(|| PathBuf::from(PROFILE_FILE))
+}
+
+/// Turns a notification stays on screen.
+const NOTIFICATION_TURNS: i32 = 10;
+
+/// Something to accomplish, over one run or many.
+pub struct Achievement {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Whether it has been earned, given the profile, the stats of the current run
+    /// and whether the run was won.
+    pub earned: fn(&Profile, &GameStats, bool) -> bool,
+}
+
+pub const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement {
+        name: "Gem Hunter",
+        description: "Retrieve the GIANT GEM",
+        earned: |_, _, victory| victory,
+    },
+    Achievement {
+        name: "Untouchable",
+        description: "Win without taking damage",
+        earned: |_, stats, victory| victory && stats.damage_taken == 0,
+    },
+    Achievement {
+        name: "Speedrunner",
+        description: "Win in under 300 steps",
+        earned: |_, stats, victory| victory && stats.steps < 300,
+    },
+    Achievement {
+        name: "Deep Delver",
+        description: "Mine 500 tiles",
+        earned: |profile, _, _| profile.tiles_mined >= 500,
+    },
+    Achievement {
+        name: "Goblin Bane",
+        description: "Slay 50 goblins",
+        earned: |profile, _, _| profile.goblins_slain >= 50,
+    },
+];
+
+/// Lifetime totals and unlocked achievements.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Profile {
+    pub tiles_mined: i32,
+    pub goblins_slain: i32,
+    pub unlocked: BTreeSet<String>,
+}
+
+impl Profile {
+    /// Unlocks every achievement newly earned, returning their names.
+    pub fn check(&mut self, stats: &GameStats, victory: bool) -> Vec<&'static str> {
+        let earned: Vec<&'static str> = ACHIEVEMENTS
+            .iter()
+            .filter(|achievement| !self.unlocked.contains(achievement.name))
+            .filter(|achievement| (achievement.earned)(self, stats, victory))
+            .map(|achievement| achievement.name)
+            .collect();
+        for name in earned.iter() {
+            self.unlocked.insert(name.to_string());
+        }
+        earned
+    }
+
+    /// Serializes the profile. One `key value` line per total and per unlock.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "tiles_mined {}\ngoblins_slain {}\n",
+            self.tiles_mined, self.goblins_slain
+        );
+        for name in self.unlocked.iter() {
+            text.push_str(&format!("unlocked {}\n", name));
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut profile = Self::default();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("bad line {}: {}", n + 1, line))?;
+            let number = || {
+                value
+                    .parse::<i32>()
+                    .map_err(|_| format!("bad number on line {}: {}", n + 1, line))
+            };
+            match key {
+                "tiles_mined" => profile.tiles_mined = number()?,
+                "goblins_slain" => profile.goblins_slain = number()?,
+                "unlocked" => {
+                    profile.unlocked.insert(value.to_string());
+                }
+                _ => return Err(format!("unknown key on line {}: {}", n + 1, key)),
+            }
+        }
+
+        Ok(profile)
+    }
+
+    /// Loads the saved profile, starting a fresh one if there is none yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_text(&text)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+/// Achievements unlocked recently, with the turns left to show each.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Notifications {
+    pub entries: Vec<(String, i32)>,
+}
+
+/// Counts lifetime totals from the events of the turn and unlocks any
+/// achievements earned, logging them and popping up a notification.
+#[system]
+#[read_component(GameEvent)]
+#[read_component(Player)]
+pub fn achievements(
+    ecs: &SubWorld,
+    #[resource] profile: &mut Profile,
+    #[resource] notifications: &mut Notifications,
+    #[resource] game_stats: &GameStats,
+    #[resource] game_log: &mut GameLog,
+    #[resource] replaying: &Replaying,
+) {
+    if replaying.0 {
+        return;
+    }
+
+    let player = <Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .copied();
+
+    <&GameEvent>::query()
+        .iter(ecs)
+        .for_each(|event| match event {
+            GameEvent::TileMined { miner, .. } if *miner == player => profile.tiles_mined += 1,
+            GameEvent::Died {
+                killer,
+                faction: Some(Faction::Goblins),
+                ..
+            } if *killer == player => profile.goblins_slain += 1,
+            _ => (),
+        });
+
+    notifications
+        .entries
+        .iter_mut()
+        .for_each(|(_, turns)| *turns -= 1);
+    notifications.entries.retain(|(_, turns)| *turns > 0);
+    let unlocked = profile.check(game_stats, false);
+    for name in unlocked.iter() {
+        game_log.log(format!("Achievement unlocked: {}!", name));
+        notifications
+            .entries
+            .push((name.to_string(), NOTIFICATION_TURNS));
+    }
+}
+
+/// Pops up recently unlocked achievements at the top of the map. Sent through a
+/// DrawBatch.
+#[system]
+pub fn notification_render(#[resource] notifications: &Notifications) {
+    let mut draw_batch = DrawBatch::new();
+
+    for (n, (name, _)) in notifications.entries.iter().enumerate() {
+        let text = format!("Achievement unlocked: {}", name);
+        let width = text.len() as i32 + 4;
+        let x = (MAP_WIDTH - width) / 2;
+        let y = 1 + n as i32 * 3;
+        draw_batch.draw_box(
+            Rect::with_size(x, y, width - 1, 2),
+            ColorPair::new(GOLD, BLACK),
+        );
+        draw_batch.print_color(Point::new(x + 2, y + 1), text, ColorPair::new(GOLD, BLACK));
+    }
+
+    draw_batch.submit(9500).expect("Batch error");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profile_test() {
+        let mut profile = Profile {
+            tiles_mined: 499,
+            ..Profile::default()
+        };
+        let mut stats = GameStats::default();
+
+        assert!(profile.check(&stats, false).is_empty());
+        profile.tiles_mined += 1;
+        assert_eq!(profile.check(&stats, false), vec!["Deep Delver"]);
+        assert!(profile.check(&stats, false).is_empty());
+
+        stats.steps = 400;
+        stats.damage_taken = 3;
+        assert_eq!(profile.check(&stats, true), vec!["Gem Hunter"]);
+
+        let loaded = Profile::from_text(&profile.to_text()).unwrap();
+        assert_eq!(loaded, profile);
+        assert!(Profile::from_text("gold 3").is_err());
+    }
+
+    #[test]
+    fn achievements_test() {
+        let mut ecs = World::default();
+        let mut resources = Resources::default();
+        resources.insert(Profile::default());
+        resources.insert(Notifications::default());
+        resources.insert(GameStats::default());
+        resources.insert(GameLog::new());
+        let player = ecs.push((Player,));
+        let mut schedule = Schedule::builder()
+            .add_system(achievements_system())
+            .add_system(clear_events_system())
+            .build();
+        let mut mine = |ecs: &mut World, resources: &mut Resources| {
+            ecs.push((
+                GameEvent::TileMined {
+                    miner: Some(player),
+                    position: Point::zero(),
+                    tile: TileType::Wall,
+                },
+                (),
+            ));
+            schedule.execute(ecs, resources);
+        };
+
+        // replays count towards nothing
+        resources.insert(Replaying(true));
+        mine(&mut ecs, &mut resources);
+        assert_eq!(resources.get::<Profile>().unwrap().tiles_mined, 0);
+
+        // runs do, without unlocking anything yet
+        resources.insert(Replaying(false));
+        mine(&mut ecs, &mut resources);
+        assert_eq!(resources.get::<Profile>().unwrap().tiles_mined, 1);
+        assert!(resources.get::<Notifications>().unwrap().entries.is_empty());
+    }
+}
@@ -17,12 +17,54 @@ pub struct Player;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Enemy;
 
+/// Side a creature fights on. Whether one faction attacks another is looked up in
+/// the HOSTILITY table, so creatures without a faction are left alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Faction {
+    /// The Player and their allies.
+    Dwarves,
+    Goblins,
+    Spiders,
+    Worms,
+    /// Creatures nobody fights, such as lost dwarves waiting to be rescued.
+    Neutral,
+}
+
+/// Pairs of factions where the first attacks the second.
+const HOSTILITY: &[(Faction, Faction)] = &[
+    (Faction::Dwarves, Faction::Goblins),
+    (Faction::Dwarves, Faction::Spiders),
+    (Faction::Dwarves, Faction::Worms),
+    (Faction::Goblins, Faction::Dwarves),
+    (Faction::Goblins, Faction::Spiders),
+    (Faction::Spiders, Faction::Dwarves),
+    (Faction::Spiders, Faction::Goblins),
+    (Faction::Worms, Faction::Dwarves),
+];
+
+impl Faction {
+    pub fn is_hostile_to(self, other: Faction) -> bool {
+        HOSTILITY.contains(&(self, other))
+    }
+}
+
 /// Entity stats used for combat.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Stats {
     pub max_health: i32,
     pub health: i32,
     pub damage: i32,
+    pub defence: i32,
+}
+
+/// Stats of an entity before any gear is counted. Stats and MineRange are derived
+/// from these and the gear equipped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BaseStats {
+    pub max_health: i32,
+    pub damage: i32,
+    pub defence: i32,
+    pub mine_range: i32,
 }
 
 /// Tag for random movement system.
@@ -33,6 +75,285 @@ pub struct RandomMovement;
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct TargetedMovement;
 
+/// Tag for monsters that dig through rock towards the player.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tunneling;
+
+/// Web tag. Webs block anything but web spinners and can be torn down or mined away.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Web;
+
+/// Tag for monsters that spin webs, move freely through them and hide in them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WebSpinner;
+
+/// Monsters that call others of their kind within range when they spot the player.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Swarm(pub i32);
+
+/// Entity is stuck in a web and cannot move for a number of turns.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Trapped {
+    pub turns: i32,
+}
+
+/// Entity is wading through slow terrain and loses its next moves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Slowed(pub i32);
+
+/// Tile about to collapse. Shown as falling dust until the ceiling comes down.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CaveIn {
+    pub turns: i32,
+}
+
+/// Entity gives off light in a radius around it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightSource {
+    pub radius: i32,
+}
+
+/// Whether a monster is drawn to or shies away from light.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightAffinity {
+    Seeks,
+    Avoids,
+}
+
+/// Something that can be carried.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Item;
+
+/// Item is being carried by an entity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Carried(pub Entity);
+
+/// Slots gear can be worn in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EquipmentSlot {
+    Weapon,
+    Armour,
+    Pickaxe,
+    Trinket,
+}
+
+impl EquipmentSlot {
+    pub const ALL: [EquipmentSlot; 4] = [
+        EquipmentSlot::Weapon,
+        EquipmentSlot::Armour,
+        EquipmentSlot::Pickaxe,
+        EquipmentSlot::Trinket,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EquipmentSlot::Weapon => "Weapon",
+            EquipmentSlot::Armour => "Armour",
+            EquipmentSlot::Pickaxe => "Pickaxe",
+            EquipmentSlot::Trinket => "Trinket",
+        }
+    }
+}
+
+/// Item can be worn in a slot.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Equippable {
+    pub slot: EquipmentSlot,
+}
+
+/// Item is being worn by an entity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Equipped(pub Entity);
+
+/// Bonuses gear adds to the stats of whoever wears it.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct StatBonus {
+    pub max_health: i32,
+    pub damage: i32,
+    pub defence: i32,
+    pub mine_range: i32,
+}
+
+impl StatBonus {
+    /// Short description of the bonuses, such as `+2 dmg, +1 def`.
+    pub fn describe(&self) -> String {
+        let mut bonuses = Vec::new();
+        if self.damage != 0 {
+            bonuses.push(format!("{:+} dmg", self.damage));
+        }
+        if self.defence != 0 {
+            bonuses.push(format!("{:+} def", self.defence));
+        }
+        if self.max_health != 0 {
+            bonuses.push(format!("{:+} max hp", self.max_health));
+        }
+        if self.mine_range != 0 {
+            bonuses.push(format!("{:+} range", self.mine_range));
+        }
+        bonuses.join(", ")
+    }
+}
+
+impl std::ops::Add for StatBonus {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            max_health: self.max_health + other.max_health,
+            damage: self.damage + other.damage,
+            defence: self.defence + other.defence,
+            mine_range: self.mine_range + other.mine_range,
+        }
+    }
+}
+
+/// Item used up in one go from the quick-use list.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Consumable {
+    /// Restores this much health.
+    Healing(i32),
+    /// Reveals the cave within this radius.
+    Mapping(i32),
+    /// Moves the user to a random spot far away.
+    Teleport,
+    /// Adds damage for a number of turns.
+    Strength { damage: i32, turns: i32 },
+    /// Places a lit bomb underfoot.
+    Bomb,
+}
+
+/// Temporary damage bonus from a strength brew.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrengthBuff {
+    pub damage: i32,
+    pub turns: i32,
+}
+
+/// Blows up everything within its radius. Set off when its Fuse burns down or when
+/// caught in another blast.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Explosive {
+    pub radius: i32,
+    pub damage: i32,
+}
+
+/// Turns left before a lit explosive blows up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fuse(pub i32);
+
+/// Orders the Player can give their allies.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllyOrder {
+    Follow,
+    Stay,
+}
+
+/// Companion fighting on the Player's side.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ally {
+    pub order: AllyOrder,
+}
+
+/// Friendly creature that becomes an ally when the Player walks into it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Recruitable;
+
+/// Kinds of trap set in the cave floor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TrapKind {
+    /// Pressure plate that brings the ceiling down, burying the tile in rubble.
+    Rockfall,
+    /// Fires a dart every time it is stepped on.
+    Dart,
+    /// Hurts whatever falls in, which then has to climb out.
+    Pit,
+}
+
+/// Trap that springs on anything stepping onto it, monsters included.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Trap {
+    pub kind: TrapKind,
+}
+
+/// Not yet noticed by the Player, and not drawn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hidden;
+
+/// Remains of a slain creature. Only decoration.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Corpse;
+
+/// Treasure lying on the floor, collected by the Player walking over it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Treasure {
+    pub item: LootItem,
+    pub quantity: i32,
+}
+
+/// Key of the monster loot table a creature drops from when slain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LootKey(pub String);
+
+/// Who or what last hurt an entity, credited with the kill if it dies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DamagedBy {
+    pub entity: Option<Entity>,
+    pub name: String,
+}
+
+/// Forge where the Player can craft gear.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Forge;
+
+/// Placed torch.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Torch;
+
+/// Number of torches carried.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Torches(pub i32);
+
+/// Boss monster that fights in phases as its health drops.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Boss {
+    pub phase: i32,
+    pub home: Point,
+    pub turns: i32,
+}
+
+/// Awareness states for monsters that perceive the player.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AwarenessState {
+    Idle,
+    Alerted,
+    Hunting,
+    Searching,
+}
+
+/// What a monster knows about the player's whereabouts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Awareness {
+    pub state: AwarenessState,
+    pub sight_range: i32,
+    pub last_known: Option<Point>,
+    pub search_turns: i32,
+}
+
+impl Awareness {
+    pub fn new(sight_range: i32) -> Self {
+        Self {
+            state: AwarenessState::Idle,
+            sight_range,
+            last_known: None,
+            search_turns: 0,
+        }
+    }
+}
+
+/// Monster flees once health drops below this percent of max health.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FleeThreshold(pub i32);
+
 /// Message for entities who want to move.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct WantsToMove {
@@ -54,6 +375,37 @@ pub struct WantsToMine {
     pub target: Point,
 }
 
+/// Message for entities who want to use a consumable item.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WantsToUse {
+    pub entity: Entity,
+    pub item: Entity,
+}
+
+/// Message for a sound made somewhere in the cave. Monsters within earshot of it
+/// come to investigate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Noise {
+    pub position: Point,
+    pub volume: i32,
+}
+
+impl Noise {
+    pub const MINING: i32 = 15;
+    /// Monsters dig slowly, so it carries less far than the Player's pickaxe.
+    pub const TUNNELING: i32 = 5;
+    pub const EXPLOSION: i32 = 25;
+    pub const COMBAT: i32 = 10;
+    pub const FOOTSTEPS: i32 = 3;
+}
+
+/// Message that an entity has stepped onto a new tile.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Moved {
+    pub entity: Entity,
+    pub position: Point,
+}
+
 /// Name storage.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Name(pub String);
@@ -61,3 +413,26 @@ pub struct Name(pub String);
 /// Mining range.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct MineRange(pub i32);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hostility_test() {
+        assert!(Faction::Dwarves.is_hostile_to(Faction::Goblins));
+        assert!(Faction::Goblins.is_hostile_to(Faction::Dwarves));
+        assert!(Faction::Spiders.is_hostile_to(Faction::Goblins));
+
+        // worms only go after dwarves, and nobody fights their own kind
+        assert!(Faction::Worms.is_hostile_to(Faction::Dwarves));
+        assert!(!Faction::Worms.is_hostile_to(Faction::Goblins));
+        assert!(!Faction::Goblins.is_hostile_to(Faction::Worms));
+        assert!(!Faction::Goblins.is_hostile_to(Faction::Goblins));
+
+        // neutral creatures are left alone and leave others alone
+        assert!(!Faction::Dwarves.is_hostile_to(Faction::Neutral));
+        assert!(!Faction::Goblins.is_hostile_to(Faction::Neutral));
+        assert!(!Faction::Neutral.is_hostile_to(Faction::Dwarves));
+    }
+}
@@ -0,0 +1,317 @@
+//! Typed stream of things that happen during play.
+//!
+//! Systems publish a GameEvent by pushing it into the ecs as a message, instead of
+//! writing to the GameLog or GameStats themselves. Subscriber systems read every
+//! event of the turn, and the events are cleared at the end of the turn, so new
+//! features can react to gameplay without touching the systems publishing it.
+//!
+//! There is no event for entering a level yet, as a run is spent in a single cave
+//! and nothing happens on arrival that a subscriber could react to. One belongs
+//! here once runs go deeper than one level.
+
+use crate::prelude::*;
+
+/// Something that happened during a turn.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    /// Something took damage. The attacker is whoever is credited for it, if anyone,
+    /// and the attacker name what dealt it, such as a monster or a trap.
+    Damaged {
+        attacker: Option<Entity>,
+        attacker_name: String,
+        cause: DamageCause,
+        target: Entity,
+        target_name: String,
+        amount: i32,
+    },
+    /// A creature's health ran out.
+    Died {
+        entity: Entity,
+        name: String,
+        faction: Option<Faction>,
+        killer: Option<Entity>,
+        killer_name: String,
+    },
+    /// A tile was dug out.
+    TileMined {
+        miner: Option<Entity>,
+        position: Point,
+        tile: TileType,
+    },
+    /// A tile turned into another, by mining or otherwise.
+    TileChanged {
+        position: Point,
+        from: TileType,
+        to: TileType,
+    },
+    /// Loot came out of a mined tile.
+    LootFound {
+        finder: Entity,
+        finder_name: String,
+        item: LootItem,
+        quantity: i32,
+    },
+    /// A creature was wrapped in a web by a web spinner.
+    Webbed {
+        entity: Entity,
+        name: String,
+        spinner_name: String,
+    },
+    /// An item was picked up off the floor.
+    ItemPickedUp {
+        entity: Entity,
+        name: String,
+        item_name: String,
+    },
+}
+
+/// How damage was dealt, which decides how it is described.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DamageCause {
+    Attack,
+    Lava,
+    CaveIn,
+    Trap,
+    Explosion,
+}
+
+/// Pushes an event for subscribers to read this turn.
+pub fn publish(commands: &mut CommandBuffer, event: GameEvent) {
+    commands.push((event, ()));
+}
+
+/// Writes events to the GameLog.
+#[system]
+#[read_component(GameEvent)]
+#[read_component(Player)]
+pub fn log_events(ecs: &SubWorld, #[resource] game_log: &mut GameLog) {
+    let player = <Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .copied();
+
+    <&GameEvent>::query()
+        .iter(ecs)
+        .for_each(|event| match event {
+            GameEvent::Damaged {
+                attacker_name,
+                cause,
+                target_name,
+                amount,
+                ..
+            } => game_log.log(match cause {
+                DamageCause::Attack => format!(
+                    "{} attacked {} for {} dmg.",
+                    attacker_name, target_name, amount
+                ),
+                DamageCause::Lava => {
+                    format!("{} is burned by Lava for {} dmg.", target_name, amount)
+                }
+                DamageCause::CaveIn => format!(
+                    "{} is struck by falling rock for {} dmg.",
+                    target_name, amount
+                ),
+                DamageCause::Trap => format!(
+                    "{} takes {} dmg from the {}.",
+                    target_name, amount, attacker_name
+                ),
+                DamageCause::Explosion => {
+                    format!("{} is caught in the blast for {} dmg.", target_name, amount)
+                }
+            }),
+            GameEvent::Died {
+                entity,
+                name,
+                killer_name,
+                ..
+            } => {
+                if Some(*entity) == player {
+                    game_log.log(format!("{} was slain by {}.", name, killer_name))
+                } else {
+                    game_log.log(format!("{} has been slain.", name))
+                }
+            }
+            // only the Player collects treasure, monsters destroy any ore they dig through
+            GameEvent::LootFound {
+                finder,
+                finder_name,
+                item,
+                quantity,
+            } => {
+                if Some(*finder) == player || *item == LootItem::Health {
+                    game_log.log(format!(
+                        "{} found {} {}.",
+                        finder_name,
+                        quantity,
+                        item.name()
+                    ));
+                } else {
+                    game_log.log(format!("{} crushes the {}.", finder_name, item.name()));
+                }
+            }
+            GameEvent::Webbed {
+                name, spinner_name, ..
+            } => game_log.log(format!("{} wraps {} in a web!", spinner_name, name)),
+            GameEvent::ItemPickedUp {
+                name, item_name, ..
+            } => game_log.log(format!("{} picks up the {}.", name, item_name)),
+            _ => (),
+        });
+}
+
+/// Keeps GameStats up to date with what the Player did and what was done to them.
+#[system]
+#[read_component(GameEvent)]
+#[read_component(Player)]
+pub fn record_events(ecs: &SubWorld, #[resource] game_stats: &mut GameStats) {
+    let player = match <Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        Some(player) => *player,
+        None => return,
+    };
+
+    <&GameEvent>::query()
+        .iter(ecs)
+        .for_each(|event| match event {
+            GameEvent::Damaged {
+                attacker,
+                target,
+                amount,
+                ..
+            } => {
+                // hurting yourself, such as with your own bomb, only counts as taken
+                if *attacker == Some(player) && *target != player {
+                    game_stats.damage_dealt += amount;
+                }
+                if *target == player {
+                    game_stats.damage_taken += amount;
+                }
+            }
+            GameEvent::Died {
+                entity,
+                killer,
+                killer_name,
+                ..
+            } => {
+                if *entity == player {
+                    game_stats.slain_by = Some(killer_name.clone());
+                } else if *killer == Some(player) {
+                    game_stats.kills += 1;
+                }
+            }
+            GameEvent::TileMined { miner, tile, .. } if *miner == Some(player) => {
+                *game_stats.tiles_mined.entry(*tile).or_insert(0) += 1;
+            }
+            GameEvent::LootFound {
+                finder,
+                item,
+                quantity,
+                ..
+            } if *finder == player => game_stats.collect(*item, *quantity),
+            _ => (),
+        });
+}
+
+/// Leaves the FlowFields out of date once a tile has changed.
+#[system]
+#[read_component(GameEvent)]
+pub fn invalidate_flow_fields(ecs: &SubWorld, #[resource] flow_fields: &mut FlowFields) {
+    if <&GameEvent>::query()
+        .iter(ecs)
+        .any(|event| matches!(event, GameEvent::TileChanged { .. }))
+    {
+        flow_fields.invalidate();
+    }
+}
+
+/// Leaves the glow of tiles in the LightMap out of date once a glowing tile has
+/// changed.
+#[system]
+#[read_component(GameEvent)]
+pub fn invalidate_glow(ecs: &SubWorld, #[resource] light_map: &mut LightMap) {
+    if <&GameEvent>::query().iter(ecs).any(|event| match event {
+        GameEvent::TileChanged { from, to, .. } => from.glow_radius() > 0 || to.glow_radius() > 0,
+        _ => false,
+    }) {
+        light_map.invalidate_glow();
+    }
+}
+
+/// Removes the events of the turn once every subscriber has read them.
+#[system]
+#[read_component(GameEvent)]
+pub fn clear_events(ecs: &SubWorld, commands: &mut CommandBuffer) {
+    <Entity>::query()
+        .filter(component::<GameEvent>())
+        .iter(ecs)
+        .for_each(|event| commands.remove(*event));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn log_and_record_events_test() {
+        let mut ecs = World::default();
+        let mut resources = Resources::default();
+        resources.insert(GameLog::new());
+        resources.insert(GameStats::default());
+        let player = ecs.push((Player,));
+        let goblin = ecs.push((Enemy,));
+        let damaged = |attacker, cause, target, amount| GameEvent::Damaged {
+            attacker,
+            attacker_name: "Player".to_string(),
+            cause,
+            target,
+            target_name: "Goblin".to_string(),
+            amount,
+        };
+        ecs.extend(vec![
+            (damaged(Some(player), DamageCause::Attack, goblin, 5), ()),
+            (damaged(Some(player), DamageCause::Explosion, player, 8), ()),
+            (damaged(None, DamageCause::Lava, player, 4), ()),
+            (
+                GameEvent::Died {
+                    entity: goblin,
+                    name: "Goblin".to_string(),
+                    faction: Some(Faction::Goblins),
+                    killer: Some(player),
+                    killer_name: "Player".to_string(),
+                },
+                (),
+            ),
+        ]);
+
+        Schedule::builder()
+            .add_system(log_events_system())
+            .add_system(record_events_system())
+            .add_system(clear_events_system())
+            .build()
+            .execute(&mut ecs, &mut resources);
+
+        let game_log = resources.get::<GameLog>().unwrap();
+        assert_eq!(
+            game_log.entries,
+            vec![
+                "Player attacked Goblin for 5 dmg.",
+                "Goblin is caught in the blast for 8 dmg.",
+                "Goblin is burned by Lava for 4 dmg.",
+                "Goblin has been slain.",
+            ]
+        );
+
+        // blowing yourself up is not damage dealt
+        let game_stats = resources.get::<GameStats>().unwrap();
+        assert_eq!(game_stats.damage_dealt, 5);
+        assert_eq!(game_stats.damage_taken, 12);
+        assert_eq!(game_stats.kills, 1);
+
+        // and the events are gone once read
+        assert_eq!(<&GameEvent>::query().iter(&ecs).count(), 0);
+    }
+}
@@ -0,0 +1,180 @@
+//! Flow fields the monsters find their way by.
+//!
+//! A DijkstraMap covers the whole map, so instead of each AI system building its
+//! own every turn, the FlowFields resource keeps them between turns for all of
+//! them to share. They are only rebuilt once the Player has moved or a tile has
+//! changed since they were built.
+
+use crate::prelude::*;
+use std::collections::VecDeque;
+
+/// Furthest distance the fields are worked out to.
+const MAX_DEPTH: f32 = 1024.0;
+
+/// Shared DijkstraMaps leading towards and away from the Player, and towards
+/// points of interest.
+pub struct FlowFields {
+    /// Leads towards the Player.
+    pub towards_player: DijkstraMap,
+    /// Leads away from the Player.
+    pub flee: DijkstraMap,
+    /// Leads towards the Player through rock as well as open floor.
+    pub tunnel: DijkstraMap,
+    /// Leads towards the nearest point of interest.
+    pub interest: DijkstraMap,
+    player_pos: Option<Point>,
+    interests: Vec<Point>,
+    stale: bool,
+}
+
+impl FlowFields {
+    pub fn new() -> Self {
+        Self {
+            towards_player: DijkstraMap::new_empty(MAP_WIDTH, MAP_HEIGHT, MAX_DEPTH),
+            flee: DijkstraMap::new_empty(MAP_WIDTH, MAP_HEIGHT, MAX_DEPTH),
+            tunnel: DijkstraMap::new_empty(MAP_WIDTH, MAP_HEIGHT, MAX_DEPTH),
+            interest: DijkstraMap::new_empty(MAP_WIDTH, MAP_HEIGHT, MAX_DEPTH),
+            player_pos: None,
+            interests: Vec::new(),
+            stale: true,
+        }
+    }
+
+    /// Marks every field out of date, for when a tile has changed.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Rebuilds the fields that are out of date, given where the Player stands and
+    /// the current points of interest.
+    pub fn update(&mut self, map: &Map, player_pos: Point, interests: &[Point]) {
+        if self.is_stale(player_pos) {
+            let player_idx = [map.point2d_to_index(player_pos)];
+            self.towards_player =
+                DijkstraMap::new(MAP_WIDTH, MAP_HEIGHT, &player_idx, map, MAX_DEPTH);
+            self.tunnel = DijkstraMap::new(
+                MAP_WIDTH,
+                MAP_HEIGHT,
+                &player_idx,
+                &TunnelMap(map),
+                MAX_DEPTH,
+            );
+
+            // invert the map to the player and rescan it to get one leading away
+            // from them, which heads for open space rather than into dead ends
+            self.flee.map = self
+                .towards_player
+                .map
+                .iter()
+                .map(|v| if *v < f32::MAX { *v * -1.2 } else { *v })
+                .collect();
+            rescan(&mut self.flee, map);
+            self.player_pos = Some(player_pos);
+        }
+
+        if self.stale || self.interests != interests {
+            let idxs: Vec<usize> = interests
+                .iter()
+                .map(|pos| map.point2d_to_index(*pos))
+                .collect();
+            self.interest = DijkstraMap::new(MAP_WIDTH, MAP_HEIGHT, &idxs, map, MAX_DEPTH);
+            self.interests = interests.to_vec();
+        }
+
+        self.stale = false;
+    }
+
+    /// Whether the fields need rebuilding before they are next used.
+    pub fn is_stale(&self, player_pos: Point) -> bool {
+        self.stale || self.player_pos != Some(player_pos)
+    }
+}
+
+/// Lowers each tile of a DijkstraMap until it is no more than a step above its
+/// neighbours, so the values spread out from the lowest tiles as if they had
+/// been the starting points.
+fn rescan(dijkstra_map: &mut DijkstraMap, map: &Map) {
+    let mut open: Vec<usize> = (0..dijkstra_map.map.len())
+        .filter(|idx| dijkstra_map.map[*idx] < f32::MAX)
+        .collect();
+    open.sort_by(|a, b| dijkstra_map.map[*a].total_cmp(&dijkstra_map.map[*b]));
+    let mut open: VecDeque<usize> = open.into();
+
+    while let Some(idx) = open.pop_front() {
+        for (exit, cost) in map.get_available_exits(idx) {
+            let depth = dijkstra_map.map[idx] + cost;
+            if depth < dijkstra_map.map[exit] {
+                dijkstra_map.map[exit] = depth;
+                open.push_back(exit);
+            }
+        }
+    }
+}
+
+impl Default for FlowFields {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flow_fields_test() {
+        let map = Map::blank();
+        let mut flow_fields = FlowFields::new();
+        let player_pos = Point::new(10, 10);
+        assert!(flow_fields.is_stale(player_pos));
+
+        flow_fields.update(&map, player_pos, &[Point::new(20, 20)]);
+        assert!(!flow_fields.is_stale(player_pos));
+        let exit = DijkstraMap::find_lowest_exit(
+            &flow_fields.towards_player,
+            map.point2d_to_index(Point::new(12, 10)),
+            &map,
+        );
+        assert_eq!(exit, Some(map.point2d_to_index(Point::new(11, 10))));
+        let exit = DijkstraMap::find_lowest_exit(
+            &flow_fields.flee,
+            map.point2d_to_index(Point::new(12, 10)),
+            &map,
+        );
+        assert_eq!(exit, Some(map.point2d_to_index(Point::new(13, 10))));
+
+        // moving or a changed tile leaves the fields out of date
+        assert!(flow_fields.is_stale(Point::new(11, 10)));
+        flow_fields.invalidate();
+        assert!(flow_fields.is_stale(player_pos));
+    }
+
+    #[test]
+    fn flee_test() {
+        // a corridor with the Player near a dead end on the right and the long way
+        // out on the left
+        let mut map = Map::blank();
+        map.tiles.iter_mut().for_each(|tile| *tile = TileType::Wall);
+        for x in 1..=45 {
+            let idx = map.point2d_to_index(Point::new(x, 10));
+            map.tiles[idx] = TileType::Floor;
+        }
+        let mut flow_fields = FlowFields::new();
+        flow_fields.update(&map, Point::new(40, 10), &[]);
+
+        // rather than backing into the dead end, a monster there slips past the
+        // Player and runs for the open end
+        let exit = DijkstraMap::find_lowest_exit(
+            &flow_fields.flee,
+            map.point2d_to_index(Point::new(42, 10)),
+            &map,
+        );
+        assert_eq!(exit, Some(map.point2d_to_index(Point::new(41, 10))));
+        let exit = DijkstraMap::find_lowest_exit(
+            &flow_fields.flee,
+            map.point2d_to_index(Point::new(30, 10)),
+            &map,
+        );
+        assert_eq!(exit, Some(map.point2d_to_index(Point::new(29, 10))));
+    }
+}
@@ -0,0 +1,169 @@
+//! Light levels of the cave and what the Player can see by them.
+//!
+//! Light comes from LightSource entities such as the Player's lantern and placed
+//! torches, and from glowing tiles. It falls off with distance and is blocked by
+//! rock. The Player can only see lit tiles in their line of sight, and remembers
+//! tiles they have seen before.
+
+use crate::prelude::*;
+
+/// How far the Player can see into lit areas.
+pub const VIEW_RANGE: i32 = 30;
+
+/// Brightness of lit tiles at the edge of a light.
+const MIN_BRIGHTNESS: f32 = 0.35;
+
+/// Brightness of remembered tiles that are out of sight.
+const MEMORY_BRIGHTNESS: f32 = 0.2;
+
+/// Light level and visibility of each tile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightMap {
+    pub light: Vec<f32>,
+    pub visible: Vec<bool>,
+    pub revealed: Vec<bool>,
+    /// Light given off by glowing tiles, kept until a tile changes.
+    glow: Vec<f32>,
+    glow_stale: bool,
+}
+
+impl LightMap {
+    pub fn new() -> Self {
+        let size = (MAP_WIDTH * MAP_HEIGHT) as usize;
+        Self {
+            light: vec![0.0; size],
+            visible: vec![false; size],
+            revealed: vec![false; size],
+            glow: vec![0.0; size],
+            glow_stale: true,
+        }
+    }
+
+    /// Lights the tiles around a point, brightest at the source. Overlapping
+    /// lights keep the brighter level.
+    pub fn add_light(&mut self, map: &Map, pos: Point, radius: i32) {
+        spread_light(&mut self.light, map, pos, radius);
+    }
+
+    /// Marks the glow of tiles out of date, for when a glowing tile has changed.
+    pub fn invalidate_glow(&mut self) {
+        self.glow_stale = true;
+    }
+
+    /// Resets the light to the glow of tiles, working the glow out again first if
+    /// it is out of date.
+    pub fn reset_to_glow(&mut self, map: &Map) {
+        if self.glow_stale {
+            self.glow.iter_mut().for_each(|level| *level = 0.0);
+            for idx in 0..map.tiles.len() {
+                let radius = map.tiles[idx].glow_radius();
+                if radius > 0 {
+                    spread_light(&mut self.glow, map, map.index_to_point2d(idx), radius);
+                }
+            }
+            self.glow_stale = false;
+        }
+        self.light.copy_from_slice(&self.glow);
+    }
+
+    /// Light level of a tile, 0.0 is pitch black.
+    pub fn level(&self, pos: Point) -> f32 {
+        tile_index(pos).map(|idx| self.light[idx]).unwrap_or(0.0)
+    }
+
+    pub fn is_visible(&self, pos: Point) -> bool {
+        tile_index(pos)
+            .map(|idx| self.visible[idx])
+            .unwrap_or(false)
+    }
+
+    pub fn is_revealed(&self, pos: Point) -> bool {
+        tile_index(pos)
+            .map(|idx| self.revealed[idx])
+            .unwrap_or(false)
+    }
+
+    /// Tints a color by the light level of a tile. Remembered tiles out of sight
+    /// are drawn dim and grey, and unseen tiles not at all.
+    pub fn shade(&self, pos: Point, color: ColorPair) -> Option<ColorPair> {
+        let idx = tile_index(pos)?;
+        let black = RGBA::from_u8(0, 0, 0, 255);
+        if self.visible[idx] {
+            let brightness = MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * self.light[idx];
+            Some(ColorPair::new(
+                color.fg.lerp(black, 1.0 - brightness),
+                color.bg,
+            ))
+        } else if self.revealed[idx] {
+            let grey = color.fg.to_greyscale();
+            Some(ColorPair::new(
+                grey.lerp(black, 1.0 - MEMORY_BRIGHTNESS),
+                color.bg,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Lights the levels around a point, brightest at the source. Overlapping lights
+/// keep the brighter level.
+fn spread_light(levels: &mut [f32], map: &Map, pos: Point, radius: i32) {
+    for pt in field_of_view_set(pos, radius, map) {
+        if !map.in_bounds(pt) {
+            continue;
+        }
+
+        let distance = DistanceAlg::Pythagoras.distance2d(pos, pt);
+        let level = 1.0 - distance / (radius as f32 + 1.0);
+        let idx = map.point2d_to_index(pt);
+        if level > levels[idx] {
+            levels[idx] = level;
+        }
+    }
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn light_map_test() {
+        let map = Map::blank();
+        let mut light_map = LightMap::new();
+        let pos = Point::new(10, 10);
+
+        light_map.add_light(&map, pos, 4);
+        assert!(light_map.level(pos) > light_map.level(Point::new(13, 10)));
+        assert!(light_map.level(Point::new(13, 10)) > 0.0);
+        assert_eq!(light_map.level(Point::new(20, 10)), 0.0);
+
+        // unseen tiles are not drawn, remembered ones are
+        let color = ColorPair::new(WHITE, BLACK);
+        assert_eq!(light_map.shade(pos, color), None);
+        let idx = map.point2d_to_index(pos);
+        light_map.revealed[idx] = true;
+        assert!(light_map.shade(pos, color).is_some());
+
+        // glow is kept until invalidated
+        let mut map = map;
+        let lava = Point::new(30, 30);
+        let lava_idx = map.point2d_to_index(lava);
+        map.tiles[lava_idx] = TileType::Lava;
+        light_map.reset_to_glow(&map);
+        assert!(light_map.level(lava) > 0.0);
+        assert_eq!(light_map.level(pos), 0.0);
+        map.tiles[lava_idx] = TileType::Floor;
+        light_map.reset_to_glow(&map);
+        assert!(light_map.level(lava) > 0.0);
+        light_map.invalidate_glow();
+        light_map.reset_to_glow(&map);
+        assert_eq!(light_map.level(lava), 0.0);
+    }
+}
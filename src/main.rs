@@ -1,7 +1,16 @@
 //! A Roguelike game using bracket-lib and legion ecs.
 
+mod achievements;
 mod components;
+mod events;
+mod flow_fields;
+mod lighting;
 mod map;
+mod morgue;
+mod occupancy;
+mod prefabs;
+mod raws;
+mod replay;
 mod schedule;
 mod spawner;
 mod systems;
@@ -22,8 +31,17 @@ mod prelude {
     pub const MAP_WIDTH: i32 = 75;
     pub const MAP_HEIGHT: i32 = 45;
 
+    pub use crate::achievements::*;
     pub use crate::components::*;
+    pub use crate::events::*;
+    pub use crate::flow_fields::*;
+    pub use crate::lighting::*;
     pub use crate::map::*;
+    pub use crate::morgue::*;
+    pub use crate::occupancy::*;
+    pub use crate::prefabs::*;
+    pub use crate::raws::*;
+    pub use crate::replay::*;
     pub use crate::schedule::*;
     pub use crate::spawner::*;
     pub use crate::systems::*;
@@ -31,60 +49,109 @@ mod prelude {
 }
 
 use prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Screens shown outside of a run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Menu {
+    Main,
+    Achievements,
+}
 
 /// Holds ecs, resources, and schedules neccessary for the game.
 struct State {
     ecs: World,
     resources: Resources,
     input_systems: Schedule,
+    crafting_systems: Schedule,
+    equipment_systems: Schedule,
     player_systems: Schedule,
     enemy_systems: Schedule,
+    /// Where the morgue file and recording of the run were saved, or why they could
+    /// not be, once the run has ended.
+    saved: Option<Vec<String>>,
+    recording: Recording,
+    replay: Option<Replay>,
+    /// The profile as last saved, and where it is saved to. Without a path the
+    /// profile is kept in memory only.
+    profile: Profile,
+    profile_path: Option<PathBuf>,
+    menu: Option<Menu>,
+    unlocked: Vec<&'static str>,
 }
 
 impl State {
-    fn new() -> Self {
-        let mut ecs = World::default();
-        let mut resources = Resources::default();
-        let mut rng = RandomNumberGenerator::new();
-        let map = Map::new(&mut rng);
-        let mut game_log = GameLog::new();
-        let game_stats = GameStats::default();
-        let lock = ClickLock(true);
-        let input_values = InputValues::default();
-
-        // initial messages in combat log.
-        game_log.log("Welcome to Dwarf Game. You are a Dwarf.".to_string());
-        game_log.log("WASD to move around. Click on tiles to mine them.".to_string());
-        game_log.log("Find the GIANT GEM to prove your worth and win!".to_string());
-
-        // spawn entities
-        spawn_player(&mut ecs, map.player_spawn_point);
-        spawn_enemies(&mut ecs, &map.enemy_spawns, &mut rng);
-
-        // insert resources
-        resources.insert(map);
-        resources.insert(TurnState::Input);
-        resources.insert(rng);
-        resources.insert(game_log);
-        resources.insert(game_stats);
-        resources.insert(true);
-        resources.insert(lock);
-        resources.insert(input_values);
-
-        Self {
-            ecs,
-            resources,
+    /// Starts the game at the main menu, or plays back a recording. Anything that
+    /// could not be loaded is reported in the GameLog of the first run.
+    fn new(
+        profile: Result<Profile, String>,
+        profile_path: Option<PathBuf>,
+        replay: Result<Option<Recording>, String>,
+    ) -> Self {
+        let mut errors = Vec::new();
+        let profile = profile.unwrap_or_else(|e| {
+            errors.push(format!("Could not load profile: {}", e));
+            Profile::default()
+        });
+        let mut state = Self {
+            ecs: World::default(),
+            resources: Resources::default(),
             input_systems: build_input_scheduler(),
+            crafting_systems: build_crafting_scheduler(),
+            equipment_systems: build_equipment_scheduler(),
             player_systems: build_player_scheduler(),
             enemy_systems: build_enemy_scheduler(),
+            saved: None,
+            recording: Recording::default(),
+            replay: None,
+            profile,
+            profile_path,
+            menu: None,
+            unlocked: Vec::new(),
+        };
+
+        match replay {
+            Ok(Some(recording)) => state.start_replay(recording),
+            Ok(None) => {
+                state.restart();
+                state.menu = Some(Menu::Main);
+            }
+            Err(e) => {
+                errors.push(e);
+                state.restart();
+                state.menu = Some(Menu::Main);
+            }
         }
+
+        if let Some(mut game_log) = state.resources.get_mut::<GameLog>() {
+            errors.into_iter().for_each(|e| game_log.log(e));
+        }
+        state
     }
 
-    /// Restarts the game. New map generated and all stats reset.
+    /// Restarts the game with a fresh seed. New map generated and all stats reset.
     fn restart(&mut self) {
+        let seed = RandomNumberGenerator::new().next_u64();
+        self.replay = None;
+        self.start_run(seed);
+    }
+
+    /// Plays back a recorded run inside the normal game loop.
+    fn start_replay(&mut self, recording: Recording) {
+        self.start_run(recording.seed);
+        self.resources.insert(Replaying(true));
+        self.replay = Some(Replay::new(recording));
+    }
+
+    /// Sets up a new run. All randomness comes from the seed so runs can be replayed.
+    fn start_run(&mut self, seed: u64) {
+        // carry over anything unlocked during the last run, even if it was cut short
+        if let Some(profile) = self.resources.get::<Profile>() {
+            self.profile = profile.clone();
+        }
         self.ecs = World::default();
         self.resources = Resources::default();
-        let mut rng = RandomNumberGenerator::new();
+        let mut rng = RandomNumberGenerator::seeded(seed);
         let map = Map::new(&mut rng);
         let mut game_log = GameLog::new();
         let game_stats = GameStats::default();
@@ -95,10 +162,20 @@ impl State {
         game_log.log("Welcome to Dwarf Game. You are a Dwarf.".to_string());
         game_log.log("WASD to move around. Click on tiles to mine them.".to_string());
         game_log.log("Find the GIANT GEM to prove your worth and win!".to_string());
+        game_log.log("It lies in a vault guarded by the Goblin King.".to_string());
+        game_log.log("Craft gear at a forge with C, or build one with B.".to_string());
+        game_log.log("T places a torch, E shows gear, R searches for traps.".to_string());
 
         // spawn entities
         spawn_player(&mut self.ecs, map.player_spawn_point);
         spawn_enemies(&mut self.ecs, &map.enemy_spawns, &mut rng);
+        spawn_rock_worms(&mut self.ecs, &map, &mut rng);
+        spawn_consumables(&mut self.ecs, &map, &mut rng);
+        spawn_powder_kegs(&mut self.ecs, &map, &mut rng);
+        spawn_traps(&mut self.ecs, &map, &mut rng);
+        spawn_lost_miners(&mut self.ecs, &map, &mut rng);
+        spawn_goblin_king(&mut self.ecs, map.boss_spawn);
+        spawn_prefab_entities(&mut self.ecs, &map);
 
         // insert resources
         self.resources.insert(map);
@@ -108,6 +185,196 @@ impl State {
         self.resources.insert(game_stats);
         self.resources.insert(lock);
         self.resources.insert(input_values);
+        self.resources.insert(LootTables::load());
+        self.resources.insert(MonsterLoot::load());
+        self.resources.insert(Recipes::load());
+        self.resources.insert(LightMap::new());
+        self.resources.insert(Occupancy::new());
+        self.resources.insert(FlowFields::new());
+        self.resources.insert(self.profile.clone());
+        self.resources.insert(Notifications::default());
+        self.resources.insert(Replaying(false));
+
+        // light the cave for the first frame, each turn relights it after that
+        Schedule::builder()
+            .add_system(lighting_system())
+            .build()
+            .execute(&mut self.ecs, &mut self.resources);
+
+        self.saved = None;
+        self.unlocked.clear();
+        self.recording = Recording::new(seed);
+    }
+
+    /// Writes the morgue file and run recording once per run, remembering where they were saved.
+    /// Achievements earned by how the run ended are unlocked and the profile is saved.
+    fn end_run(&mut self, victory: bool) {
+        if self.saved.is_none() && self.replay.is_none() {
+            let mut saved = Vec::new();
+            let mut profile = self.resources.get::<Profile>().unwrap().clone();
+            let stats = self.resources.get::<GameStats>().unwrap().clone();
+            self.unlocked = profile.check(&stats, victory);
+            self.resources.insert(profile);
+            if let Err(e) = self.save_profile() {
+                saved.push(e);
+            }
+
+            saved.push(match write_morgue(&self.ecs, &self.resources, victory) {
+                Ok(path) => format!("Morgue file written to {}", path.display()),
+                Err(e) => format!("Could not write morgue file: {}", e),
+            });
+            saved.push(match self.recording.save() {
+                Ok(path) => format!("Replay saved to {}", path.display()),
+                Err(e) => format!("Could not save replay: {}", e),
+            });
+            self.saved = Some(saved);
+        }
+    }
+
+    /// Saves the profile if it has changed since it was last saved.
+    fn save_profile(&mut self) -> Result<(), String> {
+        let profile = match self.resources.get::<Profile>() {
+            Some(profile) if *profile != self.profile => profile.clone(),
+            _ => return Ok(()),
+        };
+        self.profile = profile;
+
+        match &self.profile_path {
+            Some(path) => self
+                .profile
+                .save(path)
+                .map_err(|e| format!("Could not save profile: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the input schedule, or the schedule of the screen that is open,
+    /// recording the input if it started a turn or opened or closed a screen.
+    fn input_turn(&mut self, input_values: InputValues) {
+        let before = self.resources.get::<TurnState>().unwrap().clone();
+        self.resources.insert(input_values.clone());
+        match before {
+            TurnState::Crafting => self
+                .crafting_systems
+                .execute(&mut self.ecs, &mut self.resources),
+            TurnState::Equipment => self
+                .equipment_systems
+                .execute(&mut self.ecs, &mut self.resources),
+            _ => self
+                .input_systems
+                .execute(&mut self.ecs, &mut self.resources),
+        }
+
+        if *self.resources.get::<TurnState>().unwrap() != before {
+            self.recording.record(&input_values);
+        }
+    }
+
+    /// Feeds recorded inputs to the input schedule instead of the keyboard and mouse.
+    fn replay_turn(&mut self, ctx: &mut BTerm) {
+        let replay = self.replay.as_mut().unwrap();
+        replay.controls(ctx.key);
+
+        let input_values = match replay.next_action() {
+            Some(action) => {
+                // recorded clicks always start a turn
+                self.resources.insert(ClickLock(true));
+                action
+            }
+            None => InputValues::default(),
+        };
+
+        self.input_turn(input_values);
+    }
+
+    /// Lists where the files of the run were saved, starting at row `y`.
+    fn print_saved(&self, ctx: &mut BTerm, y: i32) {
+        for (n, line) in self.saved.iter().flatten().enumerate() {
+            ctx.print_color_centered(y + n as i32, GRAY, BLACK, line);
+        }
+    }
+
+    /// Lists the achievements unlocked by how the run ended, starting at row `y`.
+    fn print_unlocked(&self, ctx: &mut BTerm, y: i32) {
+        for (n, name) in self.unlocked.iter().enumerate() {
+            ctx.print_color_centered(
+                y + n as i32,
+                GOLD,
+                BLACK,
+                format!("Achievement unlocked: {}", name),
+            );
+        }
+    }
+
+    /// Handles the keys shared by the GameOver and Victory screens.
+    fn end_screen_input(&mut self, ctx: &mut BTerm) {
+        match ctx.key {
+            Some(VirtualKeyCode::Space) => self.restart(),
+            Some(VirtualKeyCode::Escape) => self.menu = Some(Menu::Main),
+            _ => (),
+        }
+    }
+
+    /// Displays the main menu. Starts a new game, opens the achievements or quits.
+    fn main_menu(&mut self, ctx: &mut BTerm) {
+        let half = SCREEN_HEIGHT / 2;
+        ctx.print_color_centered(half - 8, GOLD, BLACK, "DWARF GAME");
+        ctx.print_centered(
+            half - 6,
+            "Retrieve the GIANT GEM from the depths of the cave.",
+        );
+        ctx.print_centered(half - 2, "N - New game");
+        ctx.print_centered(half, "A - Achievements");
+        ctx.print_centered(half + 2, "Q - Quit");
+
+        match ctx.key {
+            Some(VirtualKeyCode::N) => {
+                self.restart();
+                self.menu = None;
+            }
+            Some(VirtualKeyCode::A) => self.menu = Some(Menu::Achievements),
+            Some(VirtualKeyCode::Q) => ctx.quitting = true,
+            _ => (),
+        }
+    }
+
+    /// Displays every achievement, whether it is unlocked, and the lifetime totals.
+    fn achievements_menu(&mut self, ctx: &mut BTerm) {
+        ctx.print_color_centered(4, GOLD, BLACK, "Achievements");
+        for (n, achievement) in ACHIEVEMENTS.iter().enumerate() {
+            let y = 8 + n as i32 * 3;
+            let (mark, color) = if self.profile.unlocked.contains(achievement.name) {
+                ("[x]", GOLD)
+            } else {
+                ("[ ]", GRAY)
+            };
+            ctx.print_color(
+                SCREEN_WIDTH / 3,
+                y,
+                color,
+                BLACK,
+                format!("{} {}", mark, achievement.name),
+            );
+            ctx.print_color(
+                SCREEN_WIDTH / 3 + 4,
+                y + 1,
+                GRAY,
+                BLACK,
+                achievement.description,
+            );
+        }
+
+        let y = 10 + ACHIEVEMENTS.len() as i32 * 3;
+        ctx.print_centered(y, format!("Tiles Mined: {}", self.profile.tiles_mined));
+        ctx.print_centered(
+            y + 2,
+            format!("Goblins Slain: {}", self.profile.goblins_slain),
+        );
+        ctx.print_color_centered(y + 5, RED, BLACK, "Press ESC to go back");
+
+        if let Some(VirtualKeyCode::Escape) = ctx.key {
+            self.menu = Some(Menu::Main);
+        }
     }
 
     /// Displays GameOver screen. Restarts game upon SPACEBAR press.
@@ -118,18 +385,29 @@ impl State {
             half - 3,
             "You exit the cave in shame, a disgrace to Dwarfkind.",
         );
-        ctx.print_color_centered(half, RED, BLACK, "Press SPACEBAR to play again ... ");
+        ctx.print_color_centered(
+            half,
+            RED,
+            BLACK,
+            "Press SPACEBAR to play again, ESC for the main menu",
+        );
 
         // print game stats
         let stats = self.resources.get::<GameStats>().unwrap().clone();
         ctx.print_centered(half + 3, format!("Gold Collected: {}", stats.gold));
         ctx.print_centered(half + 5, format!("Enemies Slain: {}", stats.kills));
-        ctx.print_centered(half + 7, format!("Slain By: {}", stats.slain_by.unwrap()));
+        ctx.print_centered(
+            half + 7,
+            format!(
+                "Slain By: {}",
+                stats.slain_by.as_deref().unwrap_or("unknown causes")
+            ),
+        );
+        ctx.print_centered(half + 9, format!("Steps Taken: {}", stats.steps));
+        self.print_saved(ctx, half + 12);
+        self.print_unlocked(ctx, half + 16);
 
-        // restart game if key pressed
-        if let Some(VirtualKeyCode::Space) = ctx.key {
-            self.restart();
-        }
+        self.end_screen_input(ctx);
     }
 
     /// Displays Victory screen. Restarts game upon SPACEBAR press.
@@ -142,17 +420,22 @@ impl State {
             "Congradulations! You found the GIANT GEM!",
         );
         ctx.print_centered(half - 3, "You are now a dishtinguished Dwarf.");
-        ctx.print_color_centered(half, RED, BLACK, "Press SPACEBAR to play again ... ");
+        ctx.print_color_centered(
+            half,
+            RED,
+            BLACK,
+            "Press SPACEBAR to play again, ESC for the main menu",
+        );
 
         // print game stats
         let stats = self.resources.get::<GameStats>().unwrap().clone();
         ctx.print_centered(half + 3, format!("Gold Collected: {}", stats.gold));
         ctx.print_centered(half + 5, format!("Enemies Slain: {}", stats.kills));
+        ctx.print_centered(half + 7, format!("Steps Taken: {}", stats.steps));
+        self.print_saved(ctx, half + 10);
+        self.print_unlocked(ctx, half + 14);
 
-        // restart game if key pressed
-        if let Some(VirtualKeyCode::Space) = ctx.key {
-            self.restart();
-        }
+        self.end_screen_input(ctx);
     }
 }
 
@@ -167,28 +450,63 @@ impl GameState for State {
             left_click: ctx.left_click,
         };
 
-        self.resources.insert(input_values);
+        if let Some(menu) = self.menu {
+            match menu {
+                Menu::Main => self.main_menu(ctx),
+                Menu::Achievements => self.achievements_menu(ctx),
+            }
+            return;
+        }
+
+        // leave replay mode
+        if self.replay.is_some() && ctx.key == Some(VirtualKeyCode::Escape) {
+            self.restart();
+        }
 
         let turnstate = self.resources.get::<TurnState>().unwrap().clone();
-        // println!("{:?}", turnstate);
         match turnstate {
-            TurnState::Input => self
-                .input_systems
-                .execute(&mut self.ecs, &mut self.resources),
-            TurnState::Player => self
-                .player_systems
-                .execute(&mut self.ecs, &mut self.resources),
+            TurnState::Input | TurnState::Crafting | TurnState::Equipment
+                if self.replay.is_some() =>
+            {
+                self.replay_turn(ctx)
+            }
+            TurnState::Input | TurnState::Crafting | TurnState::Equipment => {
+                self.input_turn(input_values)
+            }
+            TurnState::Player => {
+                self.player_systems
+                    .execute(&mut self.ecs, &mut self.resources);
+                if let Err(e) = self.save_profile() {
+                    if let Some(mut game_log) = self.resources.get_mut::<GameLog>() {
+                        game_log.log(e);
+                    }
+                }
+            }
             TurnState::Enemy => self
                 .enemy_systems
                 .execute(&mut self.ecs, &mut self.resources),
             TurnState::GameOver => {
+                self.end_run(false);
                 self.game_over(ctx);
             }
             TurnState::Victory => {
+                self.end_run(true);
                 self.victory(ctx);
             }
         }
 
+        // replay status and controls
+        if let Some(replay) = &self.replay {
+            let mut draw_batch = DrawBatch::new();
+            draw_batch.print_color(
+                Point::new(MAP_WIDTH + 2, SCREEN_HEIGHT - 4),
+                replay.status(),
+                ColorPair::new(YELLOW, BLACK),
+            );
+            draw_batch.print(Point::new(MAP_WIDTH + 2, SCREEN_HEIGHT - 3), "P/N/F/ESC");
+            draw_batch.submit(10000).expect("Batch error");
+        }
+
         render_draw_buffer(ctx).expect("Render error");
     }
 }
@@ -205,6 +523,68 @@ fn main() -> BError {
     // add cool scanlines and screen burn effect.
     ctx.with_post_scanlines(true);
 
+    // replay a recorded run with: cargo run -- --replay <file>
+    let args: Vec<String> = std::env::args().collect();
+    let replay = match args.iter().position(|arg| arg == "--replay") {
+        Some(n) => match args.get(n + 1) {
+            Some(path) => Recording::load(Path::new(path))
+                .map(Some)
+                .map_err(|e| format!("Could not load replay: {}", e)),
+            None => Err("Usage: --replay <file>".to_string()),
+        },
+        None => Ok(None),
+    };
+
+    let profile_path = profile_path();
+    let profile = Profile::load(&profile_path);
+
     // run main loop
-    main_loop(ctx, State::new())
+    main_loop(ctx, State::new(profile, Some(profile_path), replay))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn play_turns_test() {
+        let mut state = State::new(Ok(Profile::default()), None, Ok(None));
+        let mut rng = RandomNumberGenerator::seeded(7);
+        let keys = [
+            VirtualKeyCode::W,
+            VirtualKeyCode::A,
+            VirtualKeyCode::S,
+            VirtualKeyCode::D,
+            VirtualKeyCode::B,
+            VirtualKeyCode::C,
+            VirtualKeyCode::E,
+            VirtualKeyCode::T,
+            VirtualKeyCode::R,
+            VirtualKeyCode::F,
+            VirtualKeyCode::Key1,
+            VirtualKeyCode::Escape,
+        ];
+
+        // wander around with random moves and clicks, restarting whenever the run ends
+        for _ in 0..300 {
+            let turnstate = state.resources.get::<TurnState>().unwrap().clone();
+            match turnstate {
+                TurnState::Input | TurnState::Crafting | TurnState::Equipment => {
+                    let input_values = InputValues {
+                        key: rng.random_slice_entry(&keys).copied(),
+                        mouse_pos: (rng.range(0, MAP_WIDTH), rng.range(0, MAP_HEIGHT)),
+                        left_click: rng.range(0, 4) == 0,
+                    };
+                    state.input_turn(input_values);
+                }
+                TurnState::Player => state
+                    .player_systems
+                    .execute(&mut state.ecs, &mut state.resources),
+                TurnState::Enemy => state
+                    .enemy_systems
+                    .execute(&mut state.ecs, &mut state.resources),
+                _ => state.restart(),
+            }
+        }
+    }
 }
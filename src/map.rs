@@ -3,13 +3,59 @@
 use crate::prelude::*;
 
 /// Tiletypes used in the map.
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TileType {
     Wall,
     Floor,
     Gold,
     RedCrystal,
     GiantGem,
+    VaultWall,
+    VaultDoor,
+    Water,
+    Lava,
+    Ice,
+    Rubble,
+    Bedrock,
+}
+
+impl TileType {
+    /// Tiles that can be walked over.
+    pub fn is_walkable(&self) -> bool {
+        matches!(
+            self,
+            TileType::Floor | TileType::Water | TileType::Lava | TileType::Ice | TileType::Rubble
+        )
+    }
+
+    /// Pathfinding cost of entering the tile. Monsters go around slow or dangerous terrain.
+    pub fn move_cost(&self) -> f32 {
+        match self {
+            TileType::Water => 2.0,
+            TileType::Rubble => 3.0,
+            TileType::Lava => 8.0,
+            _ => 1.0,
+        }
+    }
+
+    /// Radius of the light given off by glowing tiles.
+    pub fn glow_radius(&self) -> i32 {
+        match self {
+            TileType::RedCrystal => 2,
+            TileType::Lava => 3,
+            TileType::GiantGem => 4,
+            _ => 0,
+        }
+    }
+}
+
+/// Index of a point in per-tile storage, if it lies on the map.
+pub fn tile_index(pos: Point) -> Option<usize> {
+    if pos.x < 0 || pos.y < 0 || pos.x >= MAP_WIDTH || pos.y >= MAP_HEIGHT {
+        None
+    } else {
+        Some((pos.y * MAP_WIDTH + pos.x) as usize)
+    }
 }
 
 /// Map generation and storage struct.
@@ -17,29 +63,36 @@ pub struct Map {
     pub tiles: Vec<TileType>,
     pub player_spawn_point: Point,
     pub enemy_spawns: Vec<Point>,
+    pub vault: Rect,
+    pub vault_doors: Vec<Point>,
+    pub boss_spawn: Point,
+    pub prefab_spawns: Vec<(Point, char)>,
 }
 
 /// BaseMap implementation for use with DijkstraMap.
 impl BaseMap for Map {
+    fn is_opaque(&self, idx: usize) -> bool {
+        !self.tiles[idx].is_walkable()
+    }
+
     fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
         let mut exits = SmallVec::new();
         let pos = self.index_to_point2d(idx);
-        let default_cost = 1.0;
 
         if let Some(idx) = self.can_exit(pos, Point::new(1, 0)) {
-            exits.push((idx, default_cost));
+            exits.push((idx, self.tiles[idx].move_cost()));
         }
 
         if let Some(idx) = self.can_exit(pos, Point::new(-1, 0)) {
-            exits.push((idx, default_cost));
+            exits.push((idx, self.tiles[idx].move_cost()));
         }
 
         if let Some(idx) = self.can_exit(pos, Point::new(0, -1)) {
-            exits.push((idx, default_cost));
+            exits.push((idx, self.tiles[idx].move_cost()));
         }
 
         if let Some(idx) = self.can_exit(pos, Point::new(0, 1)) {
-            exits.push((idx, default_cost));
+            exits.push((idx, self.tiles[idx].move_cost()));
         }
 
         exits
@@ -57,29 +110,79 @@ impl Algorithm2D for Map {
     }
 }
 
+/// View of the map used to path monsters that dig through rock.
+/// Tunneling through a tile costs more than walking over floor.
+pub struct TunnelMap<'a>(pub &'a Map);
+
+impl BaseMap for TunnelMap<'_> {
+    fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
+        let mut exits = SmallVec::new();
+        let pos = self.0.index_to_point2d(idx);
+        let tunnel_cost = 3.0;
+
+        for delta in [
+            Point::new(1, 0),
+            Point::new(-1, 0),
+            Point::new(0, -1),
+            Point::new(0, 1),
+        ] {
+            let new_position = pos + delta;
+            if self.0.can_enter(new_position) {
+                let idx = self.0.point2d_to_index(new_position);
+                exits.push((idx, self.0.tiles[idx].move_cost()));
+            } else if self.0.can_tunnel(new_position) {
+                exits.push((self.0.point2d_to_index(new_position), tunnel_cost));
+            }
+        }
+
+        exits
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        self.0.get_pathing_distance(idx1, idx2)
+    }
+}
+
+impl Algorithm2D for TunnelMap<'_> {
+    fn dimensions(&self) -> Point {
+        self.0.dimensions()
+    }
+}
+
 impl Map {
     pub fn new(rng: &mut RandomNumberGenerator) -> Self {
-        let mut a_map = Self {
-            tiles: vec![TileType::Floor; (MAP_WIDTH * MAP_HEIGHT) as usize],
-            player_spawn_point: Point::zero(),
-            enemy_spawns: Vec::new(),
-        };
+        let mut a_map = Self::blank();
 
         a_map.initialize(rng);
         for _ in 0..5 {
             a_map.step();
         }
         a_map.player_spawn_point = player_spawn_point(&a_map);
+        spawn_ore_veins(&mut a_map, rng);
+        spawn_vault(&mut a_map, rng);
+        place_prefabs(&mut a_map, rng);
+        a_map.add_terrain(rng);
+        a_map.add_bedrock_border();
         a_map.enemy_spawns = enemy_spawns(&a_map, rng);
-        spawn_gold(&mut a_map, rng);
-        spawn_red_crystal(&mut a_map, rng);
-        spawn_giant_gem(&mut a_map, rng);
 
         a_map
     }
 
+    /// An open map of floor tiles with nothing placed on it.
+    pub fn blank() -> Self {
+        Self {
+            tiles: vec![TileType::Floor; (MAP_WIDTH * MAP_HEIGHT) as usize],
+            player_spawn_point: Point::zero(),
+            enemy_spawns: Vec::new(),
+            vault: Rect::zero(),
+            vault_doors: Vec::new(),
+            boss_spawn: Point::zero(),
+            prefab_spawns: Vec::new(),
+        }
+    }
+
     pub fn can_enter(&self, position: Point) -> bool {
-        self.in_bounds(position) && self.tiles[self.point2d_to_index(position)] == TileType::Floor
+        self.in_bounds(position) && self.tiles[self.point2d_to_index(position)].is_walkable()
     }
 
     pub fn can_exit(&self, position: Point, delta: Point) -> Option<usize> {
@@ -93,7 +196,75 @@ impl Map {
 
     pub fn can_mine(&self, target_pos: Point) -> bool {
         self.in_bounds(target_pos)
-            && self.tiles[self.point2d_to_index(target_pos)] != TileType::Floor
+            && matches!(
+                self.tiles[self.point2d_to_index(target_pos)],
+                TileType::Wall
+                    | TileType::Gold
+                    | TileType::RedCrystal
+                    | TileType::GiantGem
+                    | TileType::Rubble
+            )
+    }
+
+    /// Monsters can dig through anything minable except the Giant Gem.
+    pub fn can_tunnel(&self, target_pos: Point) -> bool {
+        self.can_mine(target_pos)
+            && self.tiles[self.point2d_to_index(target_pos)] != TileType::GiantGem
+    }
+
+    /// Floods parts of the open cave with water, lava and ice, and scatters rubble.
+    /// Terrain follows noise so it forms pools and patches, and is kept away
+    /// from the player spawn, the vault and prefab monsters.
+    fn add_terrain(&mut self, rng: &mut RandomNumberGenerator) {
+        let safe_distance = 8.0;
+        let lava_distance = 15.0;
+        let vault_area = Rect::with_size(
+            self.vault.x1 - 1,
+            self.vault.y1 - 1,
+            self.vault.width() + 2,
+            self.vault.height() + 5,
+        );
+
+        let mut noise = FastNoise::seeded(rng.next_u64());
+        noise.set_noise_type(NoiseType::Simplex);
+        noise.set_frequency(0.08);
+
+        for idx in 0..self.tiles.len() {
+            let pos = self.index_to_point2d(idx);
+            let distance = DistanceAlg::Pythagoras.distance2d(pos, self.player_spawn_point);
+            if self.tiles[idx] != TileType::Floor
+                || distance < safe_distance
+                || vault_area.point_in_rect(pos)
+                || self.prefab_spawns.iter().any(|(pt, _)| *pt == pos)
+            {
+                continue;
+            }
+
+            // sample the noise in different places for each kind of terrain
+            let x = pos.x as f32;
+            let y = pos.y as f32;
+            if noise.get_noise(x, y) > 0.55 {
+                self.tiles[idx] = TileType::Water;
+            } else if distance > lava_distance && noise.get_noise(x + 500.0, y) > 0.65 {
+                self.tiles[idx] = TileType::Lava;
+            } else if noise.get_noise(x, y + 500.0) > 0.6 {
+                self.tiles[idx] = TileType::Ice;
+            } else if rng.range(0, 40) == 0 {
+                self.tiles[idx] = TileType::Rubble;
+            }
+        }
+    }
+
+    /// Surrounds the map with unminable bedrock.
+    fn add_bedrock_border(&mut self) {
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                if x == 0 || y == 0 || x == MAP_WIDTH - 1 || y == MAP_HEIGHT - 1 {
+                    let idx = self.point2d_to_index(Point::new(x, y));
+                    self.tiles[idx] = TileType::Bedrock;
+                }
+            }
+        }
     }
 
     /// Iterate over every tile and set to Wall or Floor based
@@ -128,7 +299,7 @@ impl Map {
         count
     }
 
-    /// Proceed one time step forwards. 
+    /// Proceed one time step forwards.
     /// ### Rules
     ///     * If Wall and < 3 neighbors, become Floor.
     ///     * If Floor and > 4 neighbors, become Wall.
@@ -165,7 +336,7 @@ mod test {
         let idx = rng.random_slice_index(&my_map.tiles).unwrap();
         let pnt = my_map.index_to_point2d(idx);
 
-        if my_map.tiles[idx] == TileType::Floor {
+        if my_map.tiles[idx].is_walkable() {
             assert!(my_map.can_enter(pnt));
         } else {
             assert!(!my_map.can_enter(pnt));
@@ -175,10 +346,29 @@ mod test {
         let idx = rng.random_slice_index(&my_map.tiles).unwrap();
         let pnt = my_map.index_to_point2d(idx);
 
-        if my_map.tiles[idx] != TileType::Floor {
-            assert!(my_map.can_mine(pnt));
-        } else {
-            assert!(!my_map.can_mine(pnt));
+        match my_map.tiles[idx] {
+            TileType::Wall
+            | TileType::Gold
+            | TileType::RedCrystal
+            | TileType::GiantGem
+            | TileType::Rubble => assert!(my_map.can_mine(pnt)),
+            _ => assert!(!my_map.can_mine(pnt)),
         }
+
+        // bedrock border can never be mined
+        assert!(!my_map.can_mine(Point::new(0, 0)));
+        assert!(!my_map.can_mine(Point::new(MAP_WIDTH - 1, MAP_HEIGHT / 2)));
+
+        // vault holds the giant gem and is locked
+        let gem = my_map.vault.center();
+        assert_eq!(
+            my_map.tiles[my_map.point2d_to_index(gem)],
+            TileType::GiantGem
+        );
+        assert!(!my_map.vault_doors.is_empty());
+        my_map
+            .vault_doors
+            .iter()
+            .for_each(|door| assert!(!my_map.can_enter(*door) && !my_map.can_mine(*door)));
     }
 }
@@ -0,0 +1,202 @@
+//! Plain-text morgue file written at the end of each run.
+
+use crate::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory morgue files are written to.
+const MORGUE_DIR: &str = "morgue";
+
+/// Builds the morgue file contents from the final state of a run.
+pub fn morgue_text(ecs: &World, resources: &Resources, victory: bool) -> String {
+    let map = resources.get::<Map>().unwrap();
+    let game_stats = resources.get::<GameStats>().unwrap();
+    let game_log = resources.get::<GameLog>().unwrap();
+    let mut text = String::new();
+
+    text.push_str("Dwarf Game - Morgue File\n");
+    text.push_str("========================\n\n");
+    if victory {
+        text.push_str("Retrieved the GIANT GEM and escaped the cave.\n\n");
+    } else {
+        text.push_str(&format!(
+            "Slain by {}.\n\n",
+            game_stats.slain_by.as_deref().unwrap_or("unknown causes")
+        ));
+    }
+
+    // final player stats
+    text.push_str("-- Stats --\n");
+    if let Some(stats) = <&Stats>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        text.push_str(&format!("HP: {}/{}\n", stats.health, stats.max_health));
+        text.push_str(&format!("Damage: {}\n", stats.damage));
+        text.push_str(&format!("Defence: {}\n", stats.defence));
+    }
+    text.push('\n');
+
+    text.push_str("-- Gear --\n");
+    let mut worn = false;
+    for slot in EquipmentSlot::ALL.iter() {
+        <(&Name, &Equippable)>::query()
+            .filter(component::<Equipped>())
+            .iter(ecs)
+            .filter(|(_, equippable)| equippable.slot == *slot)
+            .for_each(|(name, _)| {
+                text.push_str(&format!("{}: {}\n", slot.name(), name.0));
+                worn = true;
+            });
+    }
+    if !worn {
+        text.push_str("None\n");
+    }
+    text.push('\n');
+
+    // run stats
+    text.push_str("-- Game Stats --\n");
+    text.push_str(&format!("Gold Collected: {}\n", game_stats.gold));
+    text.push_str(&format!("Gems Collected: {}\n", game_stats.gems));
+    text.push_str(&format!("Crystals Collected: {}\n", game_stats.crystals));
+    text.push_str(&format!("Enemies Slain: {}\n", game_stats.kills));
+    text.push_str(&format!("Steps Taken: {}\n", game_stats.steps));
+    text.push_str(&format!("Damage Dealt: {}\n", game_stats.damage_dealt));
+    text.push_str(&format!("Damage Taken: {}\n", game_stats.damage_taken));
+    text.push_str(&format!("Has Gem: {}\n\n", game_stats.has_gem));
+
+    text.push_str("-- Tiles Mined --\n");
+    if game_stats.tiles_mined.is_empty() {
+        text.push_str("None\n");
+    }
+    for (tile, count) in game_stats.tiles_mined.iter() {
+        text.push_str(&format!("{:?}: {}\n", tile, count));
+    }
+    text.push('\n');
+
+    // final map, entities drawn over tiles
+    text.push_str("-- Final Map --\n");
+    let mut grid: Vec<char> = map.tiles.iter().map(|tile| tile_char(*tile)).collect();
+    <(&Point, &Renderable)>::query()
+        .filter(component::<Enemy>())
+        .iter(ecs)
+        .for_each(|(pos, renderable)| {
+            if map.in_bounds(*pos) {
+                grid[map.point2d_to_index(*pos)] = to_char(renderable.glyph as u8);
+            }
+        });
+    <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .for_each(|pos| {
+            if map.in_bounds(*pos) {
+                grid[map.point2d_to_index(*pos)] = '@';
+            }
+        });
+    for row in grid.chunks(MAP_WIDTH as usize) {
+        text.extend(row.iter());
+        text.push('\n');
+    }
+    text.push('\n');
+
+    // full game log
+    text.push_str("-- Game Log --\n");
+    for entry in game_log.entries.iter() {
+        text.push_str(entry);
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Writes the morgue file to disk, returning the path written to.
+pub fn write_morgue(ecs: &World, resources: &Resources, victory: bool) -> std::io::Result<PathBuf> {
+    let path = timestamped_path(MORGUE_DIR, "morgue")?;
+    fs::write(&path, morgue_text(ecs, resources, victory))?;
+
+    Ok(path)
+}
+
+/// Creates the directory if needed and picks a free file name in it stamped with
+/// the current time. Files made within the same second are told apart by a counter.
+pub fn timestamped_path(dir: &str, prefix: &str) -> std::io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    fs::create_dir_all(dir)?;
+    let mut path = PathBuf::from(dir).join(format!("{}-{}.txt", prefix, timestamp));
+    let mut n = 1;
+    while path.exists() {
+        path = PathBuf::from(dir).join(format!("{}-{}-{}.txt", prefix, timestamp, n));
+        n += 1;
+    }
+
+    Ok(path)
+}
+
+/// ASCII character used for a tile in the map dump.
+fn tile_char(tile: TileType) -> char {
+    match tile {
+        TileType::Wall => '#',
+        TileType::Floor => '.',
+        TileType::Gold => '$',
+        TileType::RedCrystal => '*',
+        TileType::GiantGem => 'G',
+        TileType::VaultWall => '%',
+        TileType::VaultDoor => '+',
+        TileType::Water => '~',
+        TileType::Lava => '^',
+        TileType::Ice => '=',
+        TileType::Rubble => ':',
+        TileType::Bedrock => 'X',
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn morgue_text_test() {
+        let mut ecs = World::default();
+        let mut resources = Resources::default();
+        let mut rng = RandomNumberGenerator::new();
+        let map = Map::new(&mut rng);
+        let mut game_log = GameLog::new();
+        let mut game_stats = GameStats::default();
+
+        spawn_player(&mut ecs, map.player_spawn_point);
+        game_log.log("Goblin attacked Player for 3 dmg.".to_string());
+        game_stats.slain_by = Some("Goblin".to_string());
+        game_stats.tiles_mined.insert(TileType::Gold, 4);
+
+        resources.insert(map);
+        resources.insert(game_log);
+        resources.insert(game_stats);
+
+        let text = morgue_text(&ecs, &resources, false);
+        assert!(text.contains("Slain by Goblin."));
+        assert!(text.contains("Gold: 4"));
+        assert!(text.contains("Goblin attacked Player for 3 dmg."));
+        assert!(text.contains('@'));
+    }
+
+    #[test]
+    fn timestamped_path_test() {
+        let dir = std::env::temp_dir().join(format!("morgue-test-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+
+        // a second file made in the same second gets a name of its own
+        let first = timestamped_path(dir, "morgue").unwrap();
+        fs::write(&first, "first").unwrap();
+        let second = timestamped_path(dir, "morgue").unwrap();
+        assert_ne!(first, second);
+        assert!(!second.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! Which creatures stand on which tiles.
+//!
+//! The Occupancy index is rebuilt at the start of every turn, so finding what
+//! stands on a tile is a lookup instead of a scan over every creature. The
+//! movement system keeps it up to date as moves are made, so moves later in the
+//! turn see where earlier ones ended up.
+
+use crate::prelude::*;
+
+/// Creatures, meaning anything with Stats, on each tile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occupancy {
+    tiles: Vec<Vec<Entity>>,
+}
+
+impl Occupancy {
+    pub fn new() -> Self {
+        Self {
+            tiles: vec![Vec::new(); (MAP_WIDTH * MAP_HEIGHT) as usize],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.iter_mut().for_each(|tile| tile.clear());
+    }
+
+    pub fn add(&mut self, pos: Point, entity: Entity) {
+        if let Some(idx) = tile_index(pos) {
+            self.tiles[idx].push(entity);
+        }
+    }
+
+    pub fn remove(&mut self, pos: Point, entity: Entity) {
+        if let Some(idx) = tile_index(pos) {
+            self.tiles[idx].retain(|e| *e != entity);
+        }
+    }
+
+    /// Moves an entity from one tile to another.
+    pub fn relocate(&mut self, entity: Entity, from: Point, to: Point) {
+        self.remove(from, entity);
+        self.add(to, entity);
+    }
+
+    /// Creatures standing on a tile.
+    pub fn at(&self, pos: Point) -> &[Entity] {
+        tile_index(pos)
+            .map(|idx| &self.tiles[idx][..])
+            .unwrap_or(&[])
+    }
+
+    /// Whether a creature other than `entity` stands on a tile.
+    pub fn is_occupied(&self, pos: Point, entity: Entity) -> bool {
+        self.at(pos).iter().any(|e| *e != entity)
+    }
+}
+
+impl Default for Occupancy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn occupancy_test() {
+        let mut world = World::default();
+        let a = world.push((Point::new(1, 1),));
+        let b = world.push((Point::new(2, 1),));
+        let mut occupancy = Occupancy::new();
+
+        occupancy.add(Point::new(1, 1), a);
+        occupancy.add(Point::new(2, 1), b);
+        assert_eq!(occupancy.at(Point::new(1, 1)), &[a]);
+        assert!(!occupancy.is_occupied(Point::new(1, 1), a));
+        assert!(occupancy.is_occupied(Point::new(2, 1), a));
+
+        occupancy.relocate(a, Point::new(1, 1), Point::new(1, 2));
+        assert!(occupancy.at(Point::new(1, 1)).is_empty());
+        assert_eq!(occupancy.at(Point::new(1, 2)), &[a]);
+        assert!(occupancy.at(Point::new(-1, 0)).is_empty());
+
+        occupancy.clear();
+        assert!(occupancy.at(Point::new(2, 1)).is_empty());
+
+        // creatures swap places only when both of their moves can be made
+        let mut resources = Resources::default();
+        resources.insert(Map::blank());
+        resources.insert(GameStats::default());
+        resources.insert(GameLog::new());
+        let mut schedule = Schedule::builder().add_system(movement_system()).build();
+        let mut swap = |world: &mut World, resources: &mut Resources| {
+            let mut occupancy = Occupancy::new();
+            occupancy.add(Point::new(1, 1), a);
+            occupancy.add(Point::new(2, 1), b);
+            resources.insert(occupancy);
+            for (entity, destination) in [(a, Point::new(2, 1)), (b, Point::new(1, 1))] {
+                world.push((WantsToMove {
+                    entity,
+                    destination,
+                },));
+            }
+            schedule.execute(world, resources);
+        };
+        let position = |world: &World, entity: Entity| {
+            *world
+                .entry_ref(entity)
+                .unwrap()
+                .get_component::<Point>()
+                .unwrap()
+        };
+
+        // a slowed creature cannot make its half of the swap, so neither moves
+        world.entry(b).unwrap().add_component(Slowed(1));
+        swap(&mut world, &mut resources);
+        assert_eq!(position(&world, a), Point::new(1, 1));
+        assert_eq!(position(&world, b), Point::new(2, 1));
+        assert_eq!(
+            resources.get::<Occupancy>().unwrap().at(Point::new(1, 1)),
+            &[a]
+        );
+
+        swap(&mut world, &mut resources);
+        assert_eq!(position(&world, a), Point::new(2, 1));
+        assert_eq!(position(&world, b), Point::new(1, 1));
+    }
+}
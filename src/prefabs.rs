@@ -0,0 +1,233 @@
+//! Hand-made rooms stamped into the generated cave from ASCII templates.
+//!
+//! Templates are the `*.txt` files in `resources/prefabs/`, read when a map is
+//! generated, so rooms can be added without rebuilding. A template is named after
+//! its file, `goblin_den.txt` being the Goblin Den. Lines starting with `;` are
+//! comments.
+//! ### Legend
+//!     * `#` Wall, `.` Floor, `$` Gold, `*` Red Crystal.
+//!     * `g` Goblin, `s` Cave Spider, `w` Rock Worm, `"` Web, `f` Forge. These stand on floor.
+//!     * Space leaves the cave underneath unchanged.
+
+use crate::prelude::*;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+/// Directory the templates are read from.
+const PREFAB_DIR: &str = "resources/prefabs";
+
+/// Cells a template may use, see the legend above.
+const LEGEND: &str = "#.$*gswf\" ";
+
+/// A room template.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefab {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub cells: Vec<char>,
+}
+
+impl Prefab {
+    pub fn from_text(name: &str, text: &str) -> Result<Self, String> {
+        let rows: Vec<&str> = text.lines().filter(|line| !line.starts_with(';')).collect();
+        let width = rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        if rows.is_empty() || width == 0 {
+            return Err(format!("prefab {} is empty", name));
+        }
+
+        let mut cells = Vec::new();
+        for row in rows.iter() {
+            for c in row.chars() {
+                if !LEGEND.contains(c) {
+                    return Err(format!("prefab {} has unknown cell {:?}", name, c));
+                }
+                cells.push(c);
+            }
+            // pad short rows with cells that leave the cave unchanged
+            cells.resize(cells.len() + width - row.chars().count(), ' ');
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            width: width as i32,
+            height: rows.len() as i32,
+            cells,
+        })
+    }
+
+    fn cell(&self, x: i32, y: i32) -> char {
+        self.cells[(y * self.width + x) as usize]
+    }
+}
+
+/// Loads every template in the prefab directory, in file name order so the same
+/// seed always stamps the same rooms.
+pub fn load_prefabs() -> Vec<Prefab> {
+    let mut paths: Vec<_> = fs::read_dir(PREFAB_DIR)
+        .expect("Missing prefab directory")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("txt")))
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let text = fs::read_to_string(path).expect("Unreadable prefab");
+            Prefab::from_text(&prefab_name(path), &text).expect("Invalid prefab")
+        })
+        .collect()
+}
+
+/// Name of the template in a file, the file name in title case.
+fn prefab_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default()
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Checks if a prefab can be stamped with its top left corner at a point. It must
+/// be inside the map border, clear of the spawn, vault and other prefabs, and be
+/// carved mostly out of rock.
+fn fits(map: &Map, prefab: &Prefab, corner: Point, placed: &[Rect]) -> bool {
+    let safe_distance = 12.0;
+    let area = Rect::with_size(corner.x, corner.y, prefab.width, prefab.height);
+    let vault_area = Rect::with_size(
+        map.vault.x1 - 1,
+        map.vault.y1 - 1,
+        map.vault.width() + 2,
+        map.vault.height() + 5,
+    );
+
+    if corner.x < 1
+        || corner.y < 1
+        || area.x2 >= MAP_WIDTH
+        || area.y2 >= MAP_HEIGHT
+        || area.intersect(&vault_area)
+        || placed.iter().any(|rect| area.intersect(rect))
+        || DistanceAlg::Pythagoras.distance2d(area.center(), map.player_spawn_point) < safe_distance
+    {
+        return false;
+    }
+
+    let rock = area
+        .point_set()
+        .iter()
+        .filter(|pt| map.tiles[map.point2d_to_index(**pt)] != TileType::Floor)
+        .count();
+    rock * 2 >= (prefab.width * prefab.height) as usize
+}
+
+/// Stamps each prefab into the map where it fits, recording the monsters, webs
+/// and forges it places in `map.prefab_spawns`.
+pub fn place_prefabs(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    let attempts = 100;
+    let mut placed = Vec::new();
+
+    for prefab in load_prefabs() {
+        for _ in 0..attempts {
+            let corner = Point::new(
+                rng.range(1, MAP_WIDTH - prefab.width),
+                rng.range(1, MAP_HEIGHT - prefab.height),
+            );
+            if !fits(map, &prefab, corner, &placed) {
+                continue;
+            }
+
+            for y in 0..prefab.height {
+                for x in 0..prefab.width {
+                    let pt = corner + Point::new(x, y);
+                    let idx = map.point2d_to_index(pt);
+                    match prefab.cell(x, y) {
+                        ' ' => (),
+                        '#' => map.tiles[idx] = TileType::Wall,
+                        '$' => map.tiles[idx] = TileType::Gold,
+                        '*' => map.tiles[idx] = TileType::RedCrystal,
+                        '.' => map.tiles[idx] = TileType::Floor,
+                        c => {
+                            map.tiles[idx] = TileType::Floor;
+                            map.prefab_spawns.push((pt, c));
+                        }
+                    }
+                }
+            }
+
+            placed.push(Rect::with_size(
+                corner.x,
+                corner.y,
+                prefab.width,
+                prefab.height,
+            ));
+            break;
+        }
+    }
+}
+
+/// Spawns the monsters, webs and forges placed by prefabs.
+pub fn spawn_prefab_entities(ecs: &mut World, map: &Map) {
+    for (pt, c) in map.prefab_spawns.iter() {
+        match c {
+            'g' => spawn_goblin(ecs, *pt),
+            's' => spawn_cave_spider(ecs, *pt),
+            'w' => spawn_rock_worm(ecs, *pt),
+            '"' => {
+                ecs.push(web(*pt));
+            }
+            'f' => {
+                ecs.push(forge(*pt));
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefab_test() {
+        // every template in the directory parses, named after its file
+        let names: Vec<String> = load_prefabs()
+            .into_iter()
+            .map(|prefab| prefab.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["Abandoned Forge", "Goblin Den", "Shrine", "Treasure Room"]
+        );
+
+        let prefab = Prefab::from_text("Test", "; comment\n#.\n$g#\n").unwrap();
+        assert_eq!((prefab.width, prefab.height), (3, 2));
+        assert_eq!(prefab.cell(2, 0), ' ');
+        assert_eq!(prefab.cell(1, 1), 'g');
+        assert!(Prefab::from_text("Bad", "#x#").is_err());
+
+        // stamp into solid rock
+        let mut map = Map::blank();
+        map.tiles.iter_mut().for_each(|t| *t = TileType::Wall);
+        map.player_spawn_point = Point::new(MAP_WIDTH - 2, MAP_HEIGHT - 2);
+        let mut rng = RandomNumberGenerator::seeded(3);
+        place_prefabs(&mut map, &mut rng);
+        assert!(map.prefab_spawns.iter().any(|(_, c)| *c == 'g'));
+        assert!(map.tiles.contains(&TileType::Floor));
+    }
+}
@@ -0,0 +1,468 @@
+//! Game data loaded from the text files in `resources/`.
+
+use crate::prelude::*;
+use std::collections::HashMap;
+
+/// A named section of a data file and its rows of fields.
+pub type Section<'a> = (&'a str, Vec<Vec<&'a str>>);
+
+/// Splits a data file into `[section]` blocks of whitespace separated fields.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn parse_sections(text: &str) -> Result<Vec<Section<'_>>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((name.trim(), Vec::new()));
+        } else if let Some((_, rows)) = sections.last_mut() {
+            rows.push(line.split_whitespace().collect());
+        } else {
+            return Err(format!("line {} is outside of a section: {}", n + 1, line));
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Parses a number field, naming the section it came from on failure.
+pub fn parse_field(section: &str, field: &str) -> Result<i32, String> {
+    field
+        .parse::<i32>()
+        .map_err(|_| format!("[{}] expected a number, found {}", section, field))
+}
+
+/// Finds the TileType with the given name.
+pub fn tile_from_name(name: &str) -> Option<TileType> {
+    match name {
+        "Wall" => Some(TileType::Wall),
+        "Floor" => Some(TileType::Floor),
+        "Gold" => Some(TileType::Gold),
+        "RedCrystal" => Some(TileType::RedCrystal),
+        "GiantGem" => Some(TileType::GiantGem),
+        "VaultWall" => Some(TileType::VaultWall),
+        "VaultDoor" => Some(TileType::VaultDoor),
+        "Water" => Some(TileType::Water),
+        "Lava" => Some(TileType::Lava),
+        "Ice" => Some(TileType::Ice),
+        "Rubble" => Some(TileType::Rubble),
+        "Bedrock" => Some(TileType::Bedrock),
+        _ => None,
+    }
+}
+
+/// Items that can be found in loot tables.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LootItem {
+    Gold,
+    Gem,
+    Crystal,
+    Health,
+    GiantGem,
+    /// A random consumable.
+    Consumable,
+}
+
+impl LootItem {
+    fn from_name(name: &str) -> Option<Option<Self>> {
+        match name {
+            "nothing" => Some(None),
+            "gold" => Some(Some(LootItem::Gold)),
+            "gem" => Some(Some(LootItem::Gem)),
+            "crystal" => Some(Some(LootItem::Crystal)),
+            "health" => Some(Some(LootItem::Health)),
+            "giant_gem" => Some(Some(LootItem::GiantGem)),
+            "item" => Some(Some(LootItem::Consumable)),
+            _ => None,
+        }
+    }
+
+    /// Name used when logging what was found.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LootItem::Gold => "Gold",
+            LootItem::Gem => "Gem",
+            LootItem::Crystal => "Crystal Shard",
+            LootItem::Health => "HP",
+            LootItem::GiantGem => "GIANT GEM",
+            LootItem::Consumable => "Item",
+        }
+    }
+}
+
+/// A possible drop and how many of it drop.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct LootEntry {
+    item: Option<LootItem>,
+    weight: i32,
+    min: i32,
+    max: i32,
+}
+
+impl LootEntry {
+    fn quantity(&self, rng: &mut RandomNumberGenerator) -> i32 {
+        rng.range(self.min, self.max + 1)
+    }
+}
+
+/// Weighted drops, of which one is picked, and independently rolled bonus finds.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct LootTable {
+    entries: Vec<LootEntry>,
+    bonuses: Vec<LootEntry>,
+}
+
+impl LootTable {
+    /// Parses the rows of a `[section]` into a table.
+    fn from_rows(section: &str, rows: Vec<Vec<&str>>) -> Result<Self, String> {
+        let mut table = Self::default();
+
+        for row in rows {
+            let (bonus, fields) = match row.split_first() {
+                Some((&"bonus", rest)) => (true, rest),
+                _ => (false, &row[..]),
+            };
+            if fields.len() != 4 {
+                return Err(format!("[{}] bad loot entry: {}", section, row.join(" ")));
+            }
+
+            let entry = LootEntry {
+                item: LootItem::from_name(fields[0])
+                    .ok_or_else(|| format!("[{}] unknown item {}", section, fields[0]))?,
+                weight: parse_field(section, fields[1])?,
+                min: parse_field(section, fields[2])?,
+                max: parse_field(section, fields[3])?,
+            };
+            if entry.weight <= 0 {
+                return Err(format!(
+                    "[{}] weight must be positive: {}",
+                    section,
+                    row.join(" ")
+                ));
+            }
+            if entry.min > entry.max {
+                return Err(format!("[{}] min above max: {}", section, row.join(" ")));
+            }
+
+            if bonus {
+                table.bonuses.push(entry);
+            } else {
+                table.entries.push(entry);
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Resolves the table into a list of items and quantities.
+    pub fn roll(&self, rng: &mut RandomNumberGenerator) -> Vec<(LootItem, i32)> {
+        let mut drops = Vec::new();
+
+        let total_weight: i32 = self.entries.iter().map(|entry| entry.weight).sum();
+        if total_weight > 0 {
+            let mut roll = rng.range(0, total_weight);
+            for entry in self.entries.iter() {
+                if roll < entry.weight {
+                    if let Some(item) = entry.item {
+                        drops.push((item, entry.quantity(rng)));
+                    }
+                    break;
+                }
+                roll -= entry.weight;
+            }
+        }
+
+        // for bonuses the weight is a 1 in n chance
+        for bonus in self.bonuses.iter() {
+            if rng.range(0, bonus.weight) == 0 {
+                if let Some(item) = bonus.item {
+                    drops.push((item, bonus.quantity(rng)));
+                }
+            }
+        }
+
+        drops.retain(|(_, quantity)| *quantity > 0);
+        drops
+    }
+}
+
+/// Loot tables for each minable TileType.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct LootTables {
+    pub tiles: HashMap<TileType, LootTable>,
+}
+
+impl LootTables {
+    /// Loads the loot tables bundled with the game.
+    pub fn load() -> Self {
+        Self::from_text(include_str!("../resources/loot_tables.txt")).expect("Invalid loot tables")
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut tables = Self::default();
+
+        for (section, rows) in parse_sections(text)? {
+            let tile =
+                tile_from_name(section).ok_or_else(|| format!("unknown tile [{}]", section))?;
+            tables
+                .tiles
+                .insert(tile, LootTable::from_rows(section, rows)?);
+        }
+
+        Ok(tables)
+    }
+
+    /// Rolls the loot table of a tile. Tiles without a table drop nothing.
+    pub fn roll(&self, tile: TileType, rng: &mut RandomNumberGenerator) -> Vec<(LootItem, i32)> {
+        self.tiles
+            .get(&tile)
+            .map(|table| table.roll(rng))
+            .unwrap_or_default()
+    }
+}
+
+/// Loot tables for each kind of monster, by the key in its LootKey.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct MonsterLoot {
+    pub monsters: HashMap<String, LootTable>,
+}
+
+impl MonsterLoot {
+    /// Loads the monster loot tables bundled with the game.
+    pub fn load() -> Self {
+        Self::from_text(include_str!("../resources/monster_loot.txt"))
+            .expect("Invalid monster loot tables")
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut loot = Self::default();
+
+        for (section, rows) in parse_sections(text)? {
+            loot.monsters
+                .insert(section.to_string(), LootTable::from_rows(section, rows)?);
+        }
+
+        Ok(loot)
+    }
+
+    /// Rolls a monster loot table. Keys without a table drop nothing.
+    pub fn roll(&self, key: &str, rng: &mut RandomNumberGenerator) -> Vec<(LootItem, i32)> {
+        self.monsters
+            .get(key)
+            .map(|table| table.roll(rng))
+            .unwrap_or_default()
+    }
+}
+
+/// Noise vein settings for an ore.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OreSettings {
+    pub tile: TileType,
+    pub rarity: i32,
+    pub frequency: i32,
+    pub distance: i32,
+    pub depth: i32,
+}
+
+impl OreSettings {
+    /// Loads the ore settings bundled with the game.
+    pub fn load() -> Vec<Self> {
+        Self::from_text(include_str!("../resources/ores.txt")).expect("Invalid ore settings")
+    }
+
+    pub fn from_text(text: &str) -> Result<Vec<Self>, String> {
+        let mut ores = Vec::new();
+
+        for (section, rows) in parse_sections(text)? {
+            let mut ore = Self {
+                tile: tile_from_name(section)
+                    .ok_or_else(|| format!("unknown tile [{}]", section))?,
+                rarity: 50,
+                frequency: 80,
+                distance: 0,
+                depth: 0,
+            };
+
+            for row in rows {
+                let value = match row.as_slice() {
+                    [_, value] => parse_field(section, value)?,
+                    _ => return Err(format!("[{}] bad setting: {}", section, row.join(" "))),
+                };
+                match row[0] {
+                    "rarity" => ore.rarity = value.max(1),
+                    "frequency" => ore.frequency = value,
+                    "distance" => ore.distance = value,
+                    "depth" => ore.depth = value,
+                    other => return Err(format!("[{}] unknown setting {}", section, other)),
+                }
+            }
+
+            ores.push(ore);
+        }
+
+        Ok(ores)
+    }
+}
+
+/// Gear that can be crafted at a forge.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recipe {
+    pub name: String,
+    pub cost: Vec<(LootItem, i32)>,
+    pub slot: EquipmentSlot,
+    pub bonus: StatBonus,
+}
+
+impl Recipe {
+    /// Short description of what the recipe costs, such as `8 Gold, 1 Gem`.
+    pub fn costs(&self) -> String {
+        self.cost
+            .iter()
+            .map(|(item, amount)| format!("{} {}", amount, item.name()))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// Finds the EquipmentSlot with the given name.
+pub fn slot_from_name(name: &str) -> Option<EquipmentSlot> {
+    match name {
+        "weapon" => Some(EquipmentSlot::Weapon),
+        "armour" => Some(EquipmentSlot::Armour),
+        "pickaxe" => Some(EquipmentSlot::Pickaxe),
+        "trinket" => Some(EquipmentSlot::Trinket),
+        _ => None,
+    }
+}
+
+/// Recipes that can be crafted at a forge, in the order they are listed.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Recipes {
+    pub recipes: Vec<Recipe>,
+}
+
+impl Recipes {
+    /// Loads the recipes bundled with the game.
+    pub fn load() -> Self {
+        Self::from_text(include_str!("../resources/recipes.txt")).expect("Invalid recipes")
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut recipes = Vec::new();
+
+        for (section, rows) in parse_sections(text)? {
+            let mut cost = Vec::new();
+            let mut slot = None;
+            let mut bonus = StatBonus::default();
+
+            for row in rows {
+                match row.as_slice() {
+                    ["cost", item, amount] => {
+                        let item = match LootItem::from_name(item) {
+                            Some(Some(
+                                item @ (LootItem::Gold | LootItem::Gem | LootItem::Crystal),
+                            )) => item,
+                            _ => return Err(format!("[{}] unknown material {}", section, item)),
+                        };
+                        cost.push((item, parse_field(section, amount)?));
+                    }
+                    ["slot", name] => {
+                        slot = Some(
+                            slot_from_name(name)
+                                .ok_or_else(|| format!("[{}] unknown slot {}", section, name))?,
+                        )
+                    }
+                    ["mine_range", value] => bonus.mine_range = parse_field(section, value)?,
+                    ["damage", value] => bonus.damage = parse_field(section, value)?,
+                    ["defence", value] => bonus.defence = parse_field(section, value)?,
+                    ["max_health", value] => bonus.max_health = parse_field(section, value)?,
+                    _ => return Err(format!("[{}] bad recipe line: {}", section, row.join(" "))),
+                }
+            }
+
+            recipes.push(Recipe {
+                name: section.to_string(),
+                cost,
+                slot: slot.ok_or_else(|| format!("[{}] has no slot", section))?,
+                bonus,
+            });
+        }
+
+        Ok(Self { recipes })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loot_tables_test() {
+        // bundled tables parse
+        let tables = LootTables::load();
+        assert!(tables.tiles.contains_key(&TileType::Gold));
+
+        let tables = LootTables::from_text(
+            "[Gold]\n# comment\ngold 1 2 2\nbonus gem 1 1 1\n\n[Wall]\nnothing 1 0 0",
+        )
+        .unwrap();
+        let mut rng = RandomNumberGenerator::seeded(1);
+        assert_eq!(
+            tables.roll(TileType::Gold, &mut rng),
+            vec![(LootItem::Gold, 2), (LootItem::Gem, 1)]
+        );
+        assert!(tables.roll(TileType::Wall, &mut rng).is_empty());
+        assert!(tables.roll(TileType::Floor, &mut rng).is_empty());
+
+        assert!(LootTables::from_text("[Gold]\ngold 1 2").is_err());
+        assert!(LootTables::from_text("[Magma]\ngold 1 1 1").is_err());
+        assert!(LootTables::from_text("[Gold]\ngold 0 1 1").is_err());
+        assert!(LootTables::from_text("[Gold]\nbonus gem -2 1 1").is_err());
+        assert!(LootTables::from_text("[Gold]\ngold 1 3 2").is_err());
+    }
+
+    #[test]
+    fn monster_loot_test() {
+        let loot = MonsterLoot::load();
+        assert!(loot.monsters.contains_key("goblin_king"));
+
+        let loot = MonsterLoot::from_text("[cave_spider]\nitem 1 2 2").unwrap();
+        let mut rng = RandomNumberGenerator::seeded(1);
+        assert_eq!(
+            loot.roll("cave_spider", &mut rng),
+            vec![(LootItem::Consumable, 2)]
+        );
+        assert!(loot.roll("dwarf_miner", &mut rng).is_empty());
+        assert!(MonsterLoot::from_text("[Goblin]\nsword 1 1 1").is_err());
+    }
+
+    #[test]
+    fn recipes_test() {
+        assert!(!Recipes::load().recipes.is_empty());
+
+        let recipes =
+            Recipes::from_text("[Axe]\nslot weapon\ncost gold 5\ncost gem 1\ndamage 2").unwrap();
+        let axe = &recipes.recipes[0];
+        assert_eq!(axe.cost, vec![(LootItem::Gold, 5), (LootItem::Gem, 1)]);
+        assert_eq!(axe.slot, EquipmentSlot::Weapon);
+        assert_eq!(axe.bonus.damage, 2);
+        assert_eq!(axe.costs(), "5 Gold, 1 Gem");
+        assert!(Recipes::from_text("[Axe]\nslot weapon\ncost health 5").is_err());
+        assert!(Recipes::from_text("[Axe]\nslot weapon\nsharpness 5").is_err());
+        assert!(Recipes::from_text("[Axe]\ndamage 2").is_err());
+    }
+
+    #[test]
+    fn ore_settings_test() {
+        assert!(!OreSettings::load().is_empty());
+
+        let ores = OreSettings::from_text("[Gold]\nrarity 20\ndepth 5").unwrap();
+        assert_eq!(ores[0].tile, TileType::Gold);
+        assert_eq!((ores[0].rarity, ores[0].depth), (20, 5));
+        assert!(OreSettings::from_text("[Gold]\nshiny 3").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Input recording and deterministic replay of runs.
+//!
+//! A run is fully described by the seed used for its RandomNumberGenerator and
+//! the InputValues of every turn taken, so playing the inputs back against the
+//! same seed reproduces the run exactly.
+
+use crate::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory recordings are saved to.
+const REPLAY_DIR: &str = "replays";
+
+/// Frames between replayed turns at normal speed.
+const REPLAY_DELAY: u32 = 8;
+
+/// Keys which can be stored in a recording.
+const RECORDABLE_KEYS: [VirtualKeyCode; 47] = [
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::Tab,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::Comma,
+];
+
+/// Seed and per-turn inputs of a run.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Recording {
+    pub seed: u64,
+    pub actions: Vec<InputValues>,
+}
+
+impl Recording {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            actions: Vec::new(),
+        }
+    }
+
+    /// Stores an input which started a turn or changed the TurnState.
+    pub fn record(&mut self, input: &InputValues) {
+        self.actions.push(input.clone());
+    }
+
+    /// Serializes the recording. One `key mouse_x mouse_y click` line per turn.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("seed {}\n", self.seed);
+
+        for action in self.actions.iter() {
+            let key = match action.key {
+                Some(key) => format!("{:?}", key),
+                None => "-".to_string(),
+            };
+            text.push_str(&format!(
+                "{} {} {} {}\n",
+                key, action.mouse_pos.0, action.mouse_pos.1, action.left_click as i32
+            ));
+        }
+
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+
+        let seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed "))
+            .and_then(|seed| seed.trim().parse::<u64>().ok())
+            .ok_or_else(|| "missing seed line".to_string())?;
+
+        let mut recording = Self::new(seed);
+        for (n, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parse_err = || format!("bad action on line {}: {}", n + 2, line);
+
+            if fields.len() != 4 {
+                return Err(parse_err());
+            }
+
+            let key = match fields[0] {
+                "-" => None,
+                name => Some(key_from_name(name).ok_or_else(parse_err)?),
+            };
+            let mouse_x = fields[1].parse::<i32>().map_err(|_| parse_err())?;
+            let mouse_y = fields[2].parse::<i32>().map_err(|_| parse_err())?;
+            let left_click = fields[3] == "1";
+
+            recording.actions.push(InputValues {
+                key,
+                mouse_pos: (mouse_x, mouse_y),
+                left_click,
+            });
+        }
+
+        Ok(recording)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_text(&text)
+    }
+
+    /// Saves the recording to the replay directory, returning the path written to.
+    pub fn save(&self) -> std::io::Result<PathBuf> {
+        let path = timestamped_path(REPLAY_DIR, "replay")?;
+        fs::write(&path, self.to_text())?;
+
+        Ok(path)
+    }
+}
+
+/// Finds the key with the given Debug name.
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    RECORDABLE_KEYS
+        .iter()
+        .find(|key| format!("{:?}", key) == name)
+        .copied()
+}
+
+/// Whether the run is a replay of a recording, in which case achievements are not
+/// counted again.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Replaying(pub bool);
+
+/// Playback state of a recording.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub recording: Recording,
+    pub next: usize,
+    pub paused: bool,
+    pub fast_forward: bool,
+    step: bool,
+    timer: u32,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            next: 0,
+            paused: false,
+            fast_forward: false,
+            step: false,
+            timer: 0,
+        }
+    }
+
+    /// Handles the playback controls. P pauses, N steps one turn while paused
+    /// and F toggles fast-forward.
+    pub fn controls(&mut self, key: Option<VirtualKeyCode>) {
+        match key {
+            Some(VirtualKeyCode::P) => self.paused = !self.paused,
+            Some(VirtualKeyCode::N) => self.step = true,
+            Some(VirtualKeyCode::F) => self.fast_forward = !self.fast_forward,
+            _ => (),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.recording.actions.len()
+    }
+
+    /// Returns the next recorded input if it is due this frame.
+    pub fn next_action(&mut self) -> Option<InputValues> {
+        if self.finished() {
+            return None;
+        }
+
+        if self.paused {
+            if !self.step {
+                return None;
+            }
+            self.step = false;
+        } else {
+            self.timer += 1;
+            if !self.fast_forward && self.timer < REPLAY_DELAY {
+                return None;
+            }
+            self.timer = 0;
+        }
+
+        self.next += 1;
+        Some(self.recording.actions[self.next - 1].clone())
+    }
+
+    /// Status line shown while a replay is playing.
+    pub fn status(&self) -> String {
+        let mode = if self.finished() {
+            "Finished"
+        } else if self.paused {
+            "Paused"
+        } else if self.fast_forward {
+            "Fast"
+        } else {
+            "Playing"
+        };
+
+        format!(
+            "Replay {} {}/{}",
+            mode,
+            self.next,
+            self.recording.actions.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recording_round_trip_test() {
+        let mut recording = Recording::new(42);
+        recording.record(&InputValues {
+            key: Some(VirtualKeyCode::W),
+            mouse_pos: (3, 4),
+            left_click: false,
+        });
+        recording.record(&InputValues {
+            key: None,
+            mouse_pos: (10, 12),
+            left_click: true,
+        });
+
+        let loaded = Recording::from_text(&recording.to_text()).unwrap();
+        assert_eq!(loaded, recording);
+        assert!(Recording::from_text("W 0 0 0").is_err());
+    }
+
+    #[test]
+    fn replay_step_test() {
+        let mut recording = Recording::new(1);
+        recording.record(&InputValues::default());
+        let mut replay = Replay::new(recording);
+
+        replay.controls(Some(VirtualKeyCode::P));
+        assert_eq!(replay.next_action(), None);
+        replay.controls(Some(VirtualKeyCode::N));
+        assert_eq!(replay.next_action(), Some(InputValues::default()));
+        assert!(replay.finished());
+    }
+}
@@ -5,40 +5,120 @@ use crate::prelude::*;
 pub fn build_input_scheduler() -> Schedule {
     Schedule::builder()
         .add_system(input_system())
+        .add_system(action_input_system())
         .flush()
         .add_system(map_render_system())
+        .add_system(blast_render_system())
         .add_system(entity_render_system())
         .add_system(ui_render_system())
+        .add_system(notification_render_system())
+        .build()
+}
+
+pub fn build_crafting_scheduler() -> Schedule {
+    Schedule::builder()
+        .add_system(crafting_input_system())
+        .flush()
+        .add_system(derive_stats_system())
+        .add_system(map_render_system())
+        .add_system(blast_render_system())
+        .add_system(entity_render_system())
+        .add_system(ui_render_system())
+        .add_system(notification_render_system())
+        .add_system(crafting_render_system())
+        .build()
+}
+
+pub fn build_equipment_scheduler() -> Schedule {
+    Schedule::builder()
+        .add_system(equipment_input_system())
+        .flush()
+        .add_system(derive_stats_system())
+        .add_system(map_render_system())
+        .add_system(blast_render_system())
+        .add_system(entity_render_system())
+        .add_system(ui_render_system())
+        .add_system(notification_render_system())
+        .add_system(equipment_render_system())
         .build()
 }
 
 pub fn build_player_scheduler() -> Schedule {
     Schedule::builder()
+        .add_system(occupancy_system())
         .add_system(combat_system())
+        .add_system(use_items_system())
         .flush()
         .add_system(movement_system())
         .flush()
         .add_system(mining_system())
+        .add_system(vault_system())
+        .add_system(pickup_system())
+        .add_system(traps_system())
+        .add_system(trap_detection_system())
+        .flush()
+        .add_system(structure_system())
+        .add_system(derive_stats_system())
+        .flush()
+        .add_system(deaths_system())
         .flush()
+        .add_system(log_events_system())
+        .add_system(record_events_system())
+        .add_system(achievements_system())
+        .add_system(invalidate_flow_fields_system())
+        .add_system(invalidate_glow_system())
+        .add_system(clear_events_system())
+        .add_system(lighting_system())
         .add_system(map_render_system())
+        .add_system(blast_render_system())
         .add_system(entity_render_system())
         .add_system(ui_render_system())
+        .add_system(notification_render_system())
         .add_system(cycle_turn_system())
         .build()
 }
 
 pub fn build_enemy_scheduler() -> Schedule {
     Schedule::builder()
+        .add_system(occupancy_system())
+        .add_system(flow_fields_system())
+        .add_system(hearing_system())
+        .add_system(perception_system())
+        .add_system(swarm_system())
         .add_system(random_movement_system())
+        .add_system(spider_movement_system())
         .add_system(targeted_movement_system())
+        .add_system(tunneling_movement_system())
+        .add_system(boss_system())
+        .add_system(ally_movement_system())
         .flush()
         .add_system(movement_system())
         .flush()
         .add_system(mining_system())
+        .add_system(vault_system())
+        .add_system(explosives_system())
+        .add_system(traps_system())
+        .flush()
+        .add_system(structure_system())
+        .add_system(hazard_system())
+        .add_system(cave_in_system())
+        .add_system(buffs_system())
+        .flush()
+        .add_system(deaths_system())
         .flush()
+        .add_system(log_events_system())
+        .add_system(record_events_system())
+        .add_system(achievements_system())
+        .add_system(invalidate_flow_fields_system())
+        .add_system(invalidate_glow_system())
+        .add_system(clear_events_system())
+        .add_system(derive_stats_system())
+        .add_system(lighting_system())
         .add_system(map_render_system())
+        .add_system(blast_render_system())
         .add_system(entity_render_system())
         .add_system(ui_render_system())
+        .add_system(notification_render_system())
         .add_system(cycle_turn_system())
         .build()
 }
@@ -2,6 +2,7 @@
 //! and spawning mineable items on the map.
 
 use crate::prelude::*;
+use std::collections::VecDeque;
 
 pub fn player_spawn_point(map: &Map) -> Point {
     let center = Point::new(MAP_WIDTH / 2, MAP_HEIGHT / 2);
@@ -34,9 +35,17 @@ pub fn enemy_spawns(map: &Map, rng: &mut RandomNumberGenerator) -> Vec<Point> {
 
     // get vec of all spawnable tiles
     // only tiles that are safe distance away from player spawn
+    let vault_area = Rect::with_size(
+        map.vault.x1,
+        map.vault.y1,
+        map.vault.width(),
+        map.vault.height() + 3,
+    );
+
     let mut spawnable = Vec::new();
     for idx in 0..map.tiles.len() {
         if map.tiles[idx] == TileType::Floor
+            && !vault_area.point_in_rect(map.index_to_point2d(idx))
             && DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, map.index_to_point2d(idx))
                 as i32
                 > safe_distance
@@ -55,145 +64,152 @@ pub fn enemy_spawns(map: &Map, rng: &mut RandomNumberGenerator) -> Vec<Point> {
     spawns
 }
 
-pub fn spawn_red_crystal(map: &mut Map, rng: &mut RandomNumberGenerator) {
-    let num_spawns = 7;
-    let mut spawnable = Vec::new();
-    for idx in 0..map.tiles.len() {
-        if map.tiles[idx] == TileType::Wall {
-            spawnable.push(map.index_to_point2d(idx));
-        }
-    }
+/// Depth of each tile into the rock, the number of steps to the nearest floor tile.
+pub fn rock_depth(map: &Map) -> Vec<i32> {
+    let mut depth = vec![i32::MAX; map.tiles.len()];
+    let mut open_list = VecDeque::new();
 
-    let mut spawn_cores = Vec::new();
-    for _ in 0..num_spawns {
-        let idx = rng.random_slice_index(&spawnable).unwrap();
-        spawn_cores.push(spawnable[idx]);
-        spawnable.remove(idx);
+    for (idx, tile) in map.tiles.iter().enumerate() {
+        if *tile == TileType::Floor {
+            depth[idx] = 0;
+            open_list.push_back(idx);
+        }
     }
 
-    let mut spawns = Vec::new();
-    for core in spawn_cores {
-        let prefabs = vec![
-            // bar1
-            vec![Point::new(1, 0)],
-            // bar2
-            vec![Point::new(-1, 0)],
-            // vert1
-            vec![Point::new(0, 1)],
-            // vert2
-            vec![Point::new(0, -1)],
-            // box1
-            vec![Point::new(1, 0), Point::new(1, -1), Point::new(0, -1)],
-            // box2
-            vec![Point::new(-1, 0), Point::new(-1, -1), Point::new(0, -1)],
-            // box3
-            vec![Point::new(1, 0), Point::new(1, 1), Point::new(0, 1)],
-            // box4
-            vec![Point::new(-1, 0), Point::new(-1, 1), Point::new(0, 1)],
-        ];
-
-        spawns.push(map.point2d_to_index(core));
-
-        let choice = rng.range(0, prefabs.len());
-        prefabs[choice].iter().for_each(|pt| {
-            if map.in_bounds(core + *pt) {
-                spawns.push(map.point2d_to_index(core + *pt))
+    while let Some(idx) = open_list.pop_front() {
+        let pos = map.index_to_point2d(idx);
+        for delta in [
+            Point::new(1, 0),
+            Point::new(-1, 0),
+            Point::new(0, 1),
+            Point::new(0, -1),
+        ] {
+            let next = pos + delta;
+            if map.in_bounds(next) {
+                let next_idx = map.point2d_to_index(next);
+                if depth[next_idx] > depth[idx] + 1 {
+                    depth[next_idx] = depth[idx] + 1;
+                    open_list.push_back(next_idx);
+                }
             }
-        });
+        }
     }
 
-    spawns
-        .iter()
-        .for_each(|idx| map.tiles[*idx] = TileType::RedCrystal);
+    depth
 }
 
-pub fn spawn_gold(map: &mut Map, rng: &mut RandomNumberGenerator) {
-    let num_spawns = 9;
-    let mut spawnable = Vec::new();
+/// Places ore in veins following ridged noise. Each rock tile is scored by the
+/// noise plus bonuses for distance from spawn and depth into the rock, and the
+/// best scoring tiles become ore, as set in `resources/ores.txt`.
+pub fn spawn_ore_veins(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    let max_depth = 6;
+    let depth = rock_depth(map);
 
-    for idx in 0..map.tiles.len() {
-        if map.tiles[idx] == TileType::Wall {
-            spawnable.push(map.index_to_point2d(idx));
-        }
-    }
+    for ore in OreSettings::load() {
+        let mut noise = FastNoise::seeded(rng.next_u64());
+        noise.set_noise_type(NoiseType::SimplexFractal);
+        noise.set_fractal_type(FractalType::RigidMulti);
+        noise.set_fractal_octaves(3);
+        noise.set_frequency(ore.frequency as f32 / 1000.0);
 
-    let mut spawn_cores = Vec::new();
-    for _ in 0..num_spawns {
-        let idx = rng.random_slice_index(&spawnable).unwrap();
-        spawn_cores.push(spawnable[idx]);
-        spawnable.remove(idx);
+        let mut scored: Vec<(usize, f32)> = (0..map.tiles.len())
+            .filter(|idx| map.tiles[*idx] == TileType::Wall)
+            .map(|idx| {
+                let pos = map.index_to_point2d(idx);
+                let distance = DistanceAlg::Pythagoras.distance2d(pos, map.player_spawn_point);
+                let score = noise.get_noise(pos.x as f32, pos.y as f32)
+                    + distance * ore.distance as f32 / 1000.0
+                    + i32::min(depth[idx], max_depth) as f32 * ore.depth as f32 / 1000.0;
+                (idx, score)
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let count = scored.len() / ore.rarity as usize;
+        scored
+            .iter()
+            .take(count)
+            .for_each(|(idx, _)| map.tiles[*idx] = ore.tile);
     }
+}
 
-    let mut spawns = Vec::new();
-    for core in spawn_cores {
-        let prefabs = vec![
-            // horizontal
-            vec![Point::new(1, 0), Point::new(-1, 0)],
-            // vertical
-            vec![Point::new(0, 1), Point::new(0, -1)],
-            // right up L
-            vec![Point::new(0, -1), Point::new(1, 0)],
-            // left up L
-            vec![Point::new(0, -1), Point::new(-1, 0)],
-            // right down L
-            vec![Point::new(0, 1), Point::new(1, 0)],
-            // left down L
-            vec![Point::new(0, 1), Point::new(-1, 0)],
-            // up T
-            vec![Point::new(1, 0), Point::new(-1, 0), Point::new(0, -1)],
-            // down T
-            vec![Point::new(1, 0), Point::new(-1, 0), Point::new(0, 1)],
-            // right T
-            vec![Point::new(0, 1), Point::new(0, -1), Point::new(1, 0)],
-            // left T
-            vec![Point::new(0, 1), Point::new(0, -1), Point::new(-1, 0)],
-            // star
-            vec![
-                Point::new(0, 1),
-                Point::new(0, -1),
-                Point::new(-1, 0),
-                Point::new(1, 0),
-            ],
-        ];
-
-        spawns.push(map.point2d_to_index(core));
-
-        let choice = rng.range(0, prefabs.len());
-        prefabs[choice].iter().for_each(|pt| {
-            if map.in_bounds(core + *pt) {
-                spawns.push(map.point2d_to_index(core + *pt))
-            }
-        });
+/// Builds a locked vault holding the Giant Gem far from the player spawn,
+/// with an antechamber in front of its door for the guardian boss.
+/// ```text
+///     #######
+///     #.....#
+///     #.....#
+///     #..G..#
+///     #.....#
+///     #.....#
+///     ###+###
+///     .......
+///     ...B...
+///     .......
+/// ```
+pub fn spawn_vault(map: &mut Map, rng: &mut RandomNumberGenerator) {
+    let min_distance = 30.0;
+    let (width, height, antechamber) = (7, 7, 3);
+
+    // every top left corner the vault and antechamber fit at
+    let mut candidates = Vec::new();
+    for y in 1..MAP_HEIGHT - height - antechamber - 1 {
+        for x in 1..MAP_WIDTH - width - 1 {
+            candidates.push(Point::new(x, y));
+        }
     }
+    let distance = |pt: &Point| {
+        DistanceAlg::Pythagoras.distance2d(
+            map.player_spawn_point,
+            Point::new(pt.x + width / 2, pt.y + height / 2),
+        )
+    };
 
-    spawns
+    let spawnable: Vec<Point> = candidates
         .iter()
-        .for_each(|idx| map.tiles[*idx] = TileType::Gold);
-}
+        .filter(|pt| distance(pt) > min_distance)
+        .copied()
+        .collect();
+    let corner = match rng.random_slice_entry(&spawnable) {
+        Some(corner) => *corner,
+        None => *candidates
+            .iter()
+            .max_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap())
+            .unwrap(),
+    };
 
-pub fn spawn_giant_gem(map: &mut Map, rng: &mut RandomNumberGenerator) {
-    let min_distance = 30;
+    map.vault = Rect::with_size(corner.x, corner.y, width, height);
+    for y in corner.y..corner.y + height + antechamber {
+        for x in corner.x..corner.x + width {
+            let pt = Point::new(x, y);
+            let idx = map.point2d_to_index(pt);
+            let edge = x == corner.x
+                || x == corner.x + width - 1
+                || y == corner.y
+                || y == corner.y + height - 1;
 
-    let mut spawnable = Vec::new();
-    for idx in 0..map.tiles.len() {
-        if
-        /*map.tiles[idx] != TileType::Floor
-        &&*/
-        DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, map.index_to_point2d(idx))
-            as i32
-            > min_distance
-        {
-            spawnable.push(map.index_to_point2d(idx));
+            map.tiles[idx] = if map.vault.point_in_rect(pt) && edge {
+                TileType::VaultWall
+            } else {
+                TileType::Floor
+            };
         }
     }
 
-    let idx = rng.random_slice_index(&spawnable).unwrap();
-    map.tiles[idx] = TileType::GiantGem;
+    let door = Point::new(corner.x + width / 2, corner.y + height - 1);
+    let door_idx = map.point2d_to_index(door);
+    map.tiles[door_idx] = TileType::VaultDoor;
+    map.vault_doors = vec![door];
+
+    let gem_idx = map.point2d_to_index(map.vault.center());
+    map.tiles[gem_idx] = TileType::GiantGem;
+
+    map.boss_spawn = Point::new(door.x, door.y + 2);
 }
 
 /// Push the player into the ecs and spawn them onto the map
 pub fn spawn_player(ecs: &mut World, position: Point) {
-    ecs.push((
+    let player = ecs.push((
         Player,
         Name("Player".to_string()),
         position,
@@ -205,9 +221,25 @@ pub fn spawn_player(ecs: &mut World, position: Point) {
             max_health: 20,
             health: 20,
             damage: 5,
+            defence: 0,
+        },
+        BaseStats {
+            max_health: 20,
+            damage: 5,
+            defence: 0,
+            mine_range: 1,
         },
         MineRange(1),
+        LightSource { radius: 6 },
+        Torches(5),
+        Faction::Dwarves,
     ));
+
+    // start with a couple of bombs
+    for _ in 0..2 {
+        let (item, name, renderable, consumable) = consumable(Consumable::Bomb);
+        ecs.push((item, name, renderable, consumable, Carried(player)));
+    }
 }
 
 pub fn spawn_enemies(ecs: &mut World, spawns: &[Point], rng: &mut RandomNumberGenerator) {
@@ -220,9 +252,199 @@ pub fn spawn_enemies(ecs: &mut World, spawns: &[Point], rng: &mut RandomNumberGe
     }
 }
 
+/// Spawns rock worms inside solid rock away from the player.
+pub fn spawn_rock_worms(ecs: &mut World, map: &Map, rng: &mut RandomNumberGenerator) {
+    let safe_distance = 15;
+    let num_spawns = 3;
+
+    let mut spawnable = Vec::new();
+    for idx in 0..map.tiles.len() {
+        if map.tiles[idx] == TileType::Wall
+            && DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, map.index_to_point2d(idx))
+                as i32
+                > safe_distance
+        {
+            spawnable.push(map.index_to_point2d(idx));
+        }
+    }
+
+    for _ in 0..num_spawns {
+        if let Some(idx) = rng.random_slice_index(&spawnable) {
+            spawn_rock_worm(ecs, spawnable[idx]);
+            spawnable.remove(idx);
+        }
+    }
+}
+
+/// Scatters consumables over open floor away from the player.
+pub fn spawn_consumables(ecs: &mut World, map: &Map, rng: &mut RandomNumberGenerator) {
+    let safe_distance = 10;
+    let num_spawns = 10;
+
+    let mut spawnable = Vec::new();
+    for idx in 0..map.tiles.len() {
+        let pos = map.index_to_point2d(idx);
+        if map.tiles[idx] == TileType::Floor
+            && !map.vault.point_in_rect(pos)
+            && DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, pos) as i32
+                > safe_distance
+        {
+            spawnable.push(pos);
+        }
+    }
+
+    for _ in 0..num_spawns {
+        if let Some(idx) = rng.random_slice_index(&spawnable) {
+            ecs.push(random_consumable(spawnable[idx], rng));
+            spawnable.remove(idx);
+        }
+    }
+}
+
+/// Components of a hidden trap.
+pub fn trap(position: Point, kind: TrapKind) -> (Trap, Hidden, Name, Point, Renderable) {
+    let (name, color) = match kind {
+        TrapKind::Rockfall => ("Rockfall Trap", GRAY),
+        TrapKind::Dart => ("Dart Trap", CYAN),
+        TrapKind::Pit => ("Pit", SADDLE_BROWN),
+    };
+
+    (
+        Trap { kind },
+        Hidden,
+        Name(name.to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(color, BLACK),
+            glyph: to_cp437('^'),
+        },
+    )
+}
+
+/// Sets hidden traps in open floor away from the player.
+pub fn spawn_traps(ecs: &mut World, map: &Map, rng: &mut RandomNumberGenerator) {
+    let safe_distance = 8;
+    let num_spawns = 15;
+
+    let mut spawnable = Vec::new();
+    for idx in 0..map.tiles.len() {
+        let pos = map.index_to_point2d(idx);
+        if map.tiles[idx] == TileType::Floor
+            && !map.vault.point_in_rect(pos)
+            && DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, pos) as i32
+                > safe_distance
+        {
+            spawnable.push(pos);
+        }
+    }
+
+    for _ in 0..num_spawns {
+        if let Some(idx) = rng.random_slice_index(&spawnable) {
+            let kind = match rng.range(0, 3) {
+                0 => TrapKind::Rockfall,
+                1 => TrapKind::Dart,
+                _ => TrapKind::Pit,
+            };
+            ecs.push(trap(spawnable[idx], kind));
+            spawnable.remove(idx);
+        }
+    }
+}
+
+/// Leaves kegs of blasting powder on open floor away from the player.
+pub fn spawn_powder_kegs(ecs: &mut World, map: &Map, rng: &mut RandomNumberGenerator) {
+    let safe_distance = 10;
+    let num_spawns = 6;
+
+    let mut spawnable = Vec::new();
+    for idx in 0..map.tiles.len() {
+        let pos = map.index_to_point2d(idx);
+        if map.tiles[idx] == TileType::Floor
+            && !map.vault.point_in_rect(pos)
+            && DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, pos) as i32
+                > safe_distance
+        {
+            spawnable.push(pos);
+        }
+    }
+
+    for _ in 0..num_spawns {
+        if let Some(idx) = rng.random_slice_index(&spawnable) {
+            ecs.push(powder_keg(spawnable[idx]));
+            spawnable.remove(idx);
+        }
+    }
+}
+
+/// Spawns dwarf miners lost in the cave far from the player, waiting to be rescued.
+pub fn spawn_lost_miners(ecs: &mut World, map: &Map, rng: &mut RandomNumberGenerator) {
+    let safe_distance = 15;
+    let num_spawns = 2;
+
+    let mut spawnable = Vec::new();
+    for idx in 0..map.tiles.len() {
+        let pos = map.index_to_point2d(idx);
+        if map.tiles[idx] == TileType::Floor
+            && !map.vault.point_in_rect(pos)
+            && DistanceAlg::Pythagoras.distance2d(map.player_spawn_point, pos) as i32
+                > safe_distance
+        {
+            spawnable.push(pos);
+        }
+    }
+
+    for _ in 0..num_spawns {
+        if let Some(idx) = rng.random_slice_index(&spawnable) {
+            spawn_dwarf_miner(ecs, spawnable[idx]);
+            spawnable.remove(idx);
+        }
+    }
+}
+
+pub fn spawn_dwarf_miner(ecs: &mut World, position: Point) {
+    ecs.push((
+        Recruitable,
+        Faction::Neutral,
+        Name("Dwarf Miner".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(LIGHT_BLUE, BLACK),
+            glyph: to_cp437('d'),
+        },
+        Stats {
+            max_health: 12,
+            health: 12,
+            damage: 3,
+            defence: 0,
+        },
+    ));
+}
+
+pub fn spawn_rock_worm(ecs: &mut World, position: Point) {
+    ecs.push((
+        Enemy,
+        Faction::Worms,
+        Name("Rock Worm".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(SLATE_GRAY, BLACK),
+            glyph: to_cp437('w'),
+        },
+        Stats {
+            max_health: 10,
+            health: 10,
+            damage: 2,
+            defence: 0,
+        },
+        Tunneling,
+        LootKey("rock_worm".to_string()),
+    ));
+}
+
 pub fn spawn_cave_spider(ecs: &mut World, position: Point) {
     ecs.push((
         Enemy,
+        Faction::Spiders,
         Name("Cave Spider".to_string()),
         position,
         Renderable {
@@ -233,14 +455,55 @@ pub fn spawn_cave_spider(ecs: &mut World, position: Point) {
             max_health: 5,
             health: 5,
             damage: 1,
+            defence: 0,
         },
-        RandomMovement {},
+        WebSpinner,
+        Awareness::new(6),
+        Swarm(12),
+        LightAffinity::Avoids,
+        LootKey("cave_spider".to_string()),
     ));
+
+    // spiders start out in their lair
+    ecs.push(web(position));
+}
+
+/// Components of a web, for pushing into the ecs or a CommandBuffer.
+pub fn web(position: Point) -> (Web, Name, Point, Renderable) {
+    (
+        Web,
+        Name("Web".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(LIGHT_GRAY, BLACK),
+            glyph: to_cp437('"'),
+        },
+    )
 }
 
 pub fn spawn_goblin(ecs: &mut World, position: Point) {
-    ecs.push((
+    ecs.push(goblin(position));
+}
+
+/// Components of a goblin, for pushing into the ecs or a CommandBuffer.
+pub fn goblin(
+    position: Point,
+) -> (
+    Enemy,
+    Faction,
+    Name,
+    Point,
+    Renderable,
+    Stats,
+    TargetedMovement,
+    Awareness,
+    FleeThreshold,
+    LightAffinity,
+    LootKey,
+) {
+    (
         Enemy,
+        Faction::Goblins,
         Name("Goblin".to_string()),
         position,
         Renderable {
@@ -251,7 +514,196 @@ pub fn spawn_goblin(ecs: &mut World, position: Point) {
             max_health: 15,
             health: 15,
             damage: 3,
+            defence: 0,
         },
         TargetedMovement {},
+        Awareness::new(8),
+        FleeThreshold(35),
+        LightAffinity::Seeks,
+        LootKey("goblin".to_string()),
+    )
+}
+
+/// Components of a piece of gear carried by an entity.
+pub fn gear(
+    owner: Entity,
+    name: &str,
+    slot: EquipmentSlot,
+    bonus: StatBonus,
+) -> (Item, Name, Equippable, StatBonus, Carried) {
+    (
+        Item,
+        Name(name.to_string()),
+        Equippable { slot },
+        bonus,
+        Carried(owner),
+    )
+}
+
+/// Components of a consumable, named and drawn after what it does.
+pub fn consumable(kind: Consumable) -> (Item, Name, Renderable, Consumable) {
+    let (name, glyph, color) = match kind {
+        Consumable::Healing(_) => ("Healing Draught", '!', MAGENTA),
+        Consumable::Strength { .. } => ("Strength Brew", '!', ORANGE),
+        Consumable::Mapping(_) => ("Mapping Scroll", '?', CYAN),
+        Consumable::Teleport => ("Teleport Scroll", '?', VIOLET),
+        Consumable::Bomb => ("Bomb", 'o', GRAY),
+    };
+
+    (
+        Item,
+        Name(name.to_string()),
+        Renderable {
+            color: ColorPair::new(color, BLACK),
+            glyph: to_cp437(glyph),
+        },
+        kind,
+    )
+}
+
+/// Components of a consumable lying in the cave, picked at random. Healing
+/// draughts are the most common.
+pub fn random_consumable(
+    position: Point,
+    rng: &mut RandomNumberGenerator,
+) -> (Item, Name, Point, Renderable, Consumable) {
+    let kind = match rng.range(0, 7) {
+        0..=2 => Consumable::Healing(10),
+        3 => Consumable::Strength {
+            damage: 3,
+            turns: 20,
+        },
+        4 => Consumable::Mapping(20),
+        5 => Consumable::Teleport,
+        _ => Consumable::Bomb,
+    };
+
+    let (item, name, renderable, consumable) = consumable(kind);
+    (item, name, position, renderable, consumable)
+}
+
+/// Components of treasure dropped on the floor.
+pub fn treasure(
+    position: Point,
+    item: LootItem,
+    quantity: i32,
+) -> (Item, Name, Point, Renderable, Treasure) {
+    let (glyph, color) = match item {
+        LootItem::Gem | LootItem::GiantGem => ('*', CYAN),
+        LootItem::Crystal => ('*', RED),
+        _ => ('$', GOLD),
+    };
+
+    (
+        Item,
+        Name(item.name().to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(color, BLACK),
+            glyph: to_cp437(glyph),
+        },
+        Treasure { item, quantity },
+    )
+}
+
+/// Components of the corpse a creature leaves behind, in its own colour.
+pub fn corpse(position: Point, name: &str, color: ColorPair) -> (Corpse, Name, Point, Renderable) {
+    (
+        Corpse,
+        Name(format!("{} corpse", name)),
+        position,
+        Renderable {
+            color,
+            glyph: to_cp437('%'),
+        },
+    )
+}
+
+/// Components of a lit bomb. The burning fuse gives off a little light.
+pub fn lit_bomb(position: Point) -> (Explosive, Fuse, Name, Point, Renderable, LightSource) {
+    (
+        Explosive {
+            radius: 2,
+            damage: 8,
+        },
+        Fuse(3),
+        Name("Bomb".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(ORANGE_RED, BLACK),
+            glyph: to_cp437('o'),
+        },
+        LightSource { radius: 1 },
+    )
+}
+
+/// Components of a keg of blasting powder. Kegs have no fuse and only go off when
+/// caught in another blast.
+pub fn powder_keg(position: Point) -> (Explosive, Name, Point, Renderable) {
+    (
+        Explosive {
+            radius: 3,
+            damage: 10,
+        },
+        Name("Powder Keg".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(SADDLE_BROWN, BLACK),
+            glyph: to_cp437('0'),
+        },
+    )
+}
+
+/// Components of a forge. Forges glow from the fire burning in them.
+pub fn forge(position: Point) -> (Forge, Name, Point, Renderable, LightSource) {
+    (
+        Forge,
+        Name("Forge".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(ORANGE_RED, BLACK),
+            glyph: to_cp437('&'),
+        },
+        LightSource { radius: 3 },
+    )
+}
+
+/// Components of a torch placed in the cave.
+pub fn torch(position: Point) -> (Torch, Point, Renderable, LightSource) {
+    (
+        Torch,
+        position,
+        Renderable {
+            color: ColorPair::new(ORANGE, BLACK),
+            glyph: to_cp437('|'),
+        },
+        LightSource { radius: 5 },
+    )
+}
+
+/// Spawns the Goblin King guarding the vault.
+pub fn spawn_goblin_king(ecs: &mut World, position: Point) {
+    ecs.push((
+        Enemy,
+        Faction::Goblins,
+        Name("Goblin King".to_string()),
+        position,
+        Renderable {
+            color: ColorPair::new(GOLD, BLACK),
+            glyph: to_cp437('G'),
+        },
+        Stats {
+            max_health: 45,
+            health: 45,
+            damage: 4,
+            defence: 0,
+        },
+        Awareness::new(10),
+        Boss {
+            phase: 1,
+            home: position,
+            turns: 0,
+        },
+        LootKey("goblin_king".to_string()),
     ));
 }
@@ -1,17 +1,26 @@
 //! Legion systems which are called in a schedule.
 
 use crate::prelude::*;
+use std::collections::BTreeMap;
 
 /// Game states. Used to handle turns, loss, and win.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TurnState {
     Input,
     Player,
     Enemy,
+    Crafting,
+    Equipment,
     GameOver,
     Victory,
 }
 
+/// Gold it costs to build a forge.
+pub const FORGE_COST: i32 = 15;
+
+/// How far a search reaches.
+pub const SEARCH_RANGE: i32 = 3;
+
 /// Workaround for left_click registering twice per click in bracket-lib.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ClickLock(pub bool);
@@ -24,6 +33,53 @@ pub struct GameStats {
     pub steps: i32,
     pub slain_by: Option<String>,
     pub has_gem: bool,
+    pub gems: i32,
+    pub crystals: i32,
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub tiles_mined: BTreeMap<TileType, i32>,
+}
+
+impl GameStats {
+    /// How much of a crafting material the Player is carrying.
+    pub fn material(&self, item: LootItem) -> i32 {
+        match item {
+            LootItem::Gold => self.gold,
+            LootItem::Gem => self.gems,
+            LootItem::Crystal => self.crystals,
+            _ => 0,
+        }
+    }
+
+    pub fn can_afford(&self, recipe: &Recipe) -> bool {
+        recipe
+            .cost
+            .iter()
+            .all(|(item, amount)| self.material(*item) >= *amount)
+    }
+
+    /// Adds treasure found by the Player. Healing is not kept.
+    pub fn collect(&mut self, item: LootItem, quantity: i32) {
+        match item {
+            LootItem::Gold => self.gold += quantity,
+            LootItem::Gem => self.gems += quantity,
+            LootItem::Crystal => self.crystals += quantity,
+            LootItem::GiantGem => self.has_gem = true,
+            LootItem::Health | LootItem::Consumable => (),
+        }
+    }
+
+    /// Takes the cost of a recipe out of the Player's materials.
+    pub fn pay(&mut self, recipe: &Recipe) {
+        for (item, amount) in recipe.cost.iter() {
+            match item {
+                LootItem::Gold => self.gold -= amount,
+                LootItem::Gem => self.gems -= amount,
+                LootItem::Crystal => self.crystals -= amount,
+                _ => (),
+            }
+        }
+    }
 }
 
 /// Input values grouping to reduce function argument counts.
@@ -39,12 +95,133 @@ pub fn in_range(pos: Point, target: Point, range: i32) -> bool {
     (pos.x - target.x).abs() <= range && (pos.y - target.y).abs() <= range
 }
 
-/// Handles player input.
+/// Menu entry picked by a number key, 1 being the first entry.
+pub fn menu_index(key: VirtualKeyCode) -> Option<usize> {
+    match key {
+        VirtualKeyCode::Key1 => Some(0),
+        VirtualKeyCode::Key2 => Some(1),
+        VirtualKeyCode::Key3 => Some(2),
+        VirtualKeyCode::Key4 => Some(3),
+        VirtualKeyCode::Key5 => Some(4),
+        VirtualKeyCode::Key6 => Some(5),
+        VirtualKeyCode::Key7 => Some(6),
+        VirtualKeyCode::Key8 => Some(7),
+        VirtualKeyCode::Key9 => Some(8),
+        _ => None,
+    }
+}
+
+/// Picks a random step in one of the four cardinal directions.
+pub fn random_delta(rng: &mut RandomNumberGenerator) -> Point {
+    match rng.range(0, 4) {
+        0 => Point::new(-1, 0),
+        1 => Point::new(1, 0),
+        2 => Point::new(0, 1),
+        _ => Point::new(0, -1),
+    }
+}
+
+/// Checks if target is within sight range and line of sight of position.
+pub fn can_see(map: &Map, pos: Point, target: Point, range: i32) -> bool {
+    DistanceAlg::Pythagoras.distance2d(pos, target) <= range as f32
+        && field_of_view_set(pos, range, map).contains(&target)
+}
+
+/// Whether the faction of one entity is hostile to that of another. Entities
+/// without a faction are hostile to no one.
+pub fn is_hostile(ecs: &SubWorld, entity: Entity, target: Entity) -> bool {
+    let faction = |entity: Entity| {
+        ecs.entry_ref(entity)
+            .ok()
+            .and_then(|entry| entry.get_component::<Faction>().ok().copied())
+    };
+    match (faction(entity), faction(target)) {
+        (Some(faction), Some(other)) => faction.is_hostile_to(other),
+        _ => false,
+    }
+}
+
+/// Picks the neighbouring tile a monster with a LightAffinity would rather stand
+/// on, if it is any better lit to its liking than where it is now.
+pub fn light_step(
+    map: &Map,
+    light_map: &LightMap,
+    pos: Point,
+    affinity: LightAffinity,
+) -> Option<Point> {
+    let preference = |pt: Point| match affinity {
+        LightAffinity::Seeks => light_map.level(pt),
+        LightAffinity::Avoids => -light_map.level(pt),
+    };
+
+    [
+        Point::new(1, 0),
+        Point::new(-1, 0),
+        Point::new(0, 1),
+        Point::new(0, -1),
+    ]
+    .iter()
+    .map(|delta| pos + *delta)
+    .filter(|pt| map.can_enter(*pt) && preference(*pt) > preference(pos))
+    .max_by(|a, b| preference(*a).total_cmp(&preference(*b)))
+}
+
+/// Changes a tile, publishing the change for anything that depends on the terrain.
+pub fn set_tile(map: &mut Map, commands: &mut CommandBuffer, position: Point, tile: TileType) {
+    let idx = map.point2d_to_index(position);
+    let from = map.tiles[idx];
+    map.tiles[idx] = tile;
+    publish(
+        commands,
+        GameEvent::TileChanged {
+            position,
+            from,
+            to: tile,
+        },
+    );
+}
+
+/// Tiles caught in a blast of the given radius.
+pub fn blast_area(map: &Map, pos: Point, radius: i32) -> Vec<Point> {
+    let mut area = Vec::new();
+    for y in pos.y - radius..=pos.y + radius {
+        for x in pos.x - radius..=pos.x + radius {
+            let pt = Point::new(x, y);
+            if map.in_bounds(pt)
+                && DistanceAlg::Pythagoras.distance2d(pos, pt) <= radius as f32 + 0.5
+            {
+                area.push(pt);
+            }
+        }
+    }
+    area
+}
+
+/// Consumables carried by an entity, stacked by name in the order they are listed
+/// for quick use.
+pub fn quick_use_items(ecs: &SubWorld, owner: Entity) -> Vec<(String, Vec<Entity>)> {
+    let mut stacks: BTreeMap<String, Vec<Entity>> = BTreeMap::new();
+    <(Entity, &Name, &Carried)>::query()
+        .filter(component::<Consumable>())
+        .iter(ecs)
+        .filter(|(_, _, carried)| carried.0 == owner)
+        .for_each(|(entity, name, _)| stacks.entry(name.0.clone()).or_default().push(*entity));
+    stacks.into_iter().collect()
+}
+
+/// Handles player input. Walking into anything hostile attacks it, walking into a
+/// web cuts it away, walking into an ally swaps places with it, and walking into a
+/// lost dwarf recruits them.
 #[system]
 #[read_component(Point)]
 #[read_component(Player)]
-#[read_component(Enemy)]
+#[read_component(Faction)]
+#[read_component(Web)]
+#[read_component(Trapped)]
 #[read_component(MineRange)]
+#[read_component(Name)]
+#[read_component(Ally)]
+#[read_component(Recruitable)]
 pub fn input(
     ecs: &mut SubWorld,
     commands: &mut CommandBuffer,
@@ -52,8 +229,11 @@ pub fn input(
     #[resource] input: &InputValues,
     #[resource] turnstate: &mut TurnState,
     #[resource] lock: &mut ClickLock,
+    #[resource] game_log: &mut GameLog,
 ) {
-    let mut player = <(Entity, &Point, &MineRange)>::query().filter(component::<Player>());
+    let mut player =
+        <(Entity, &Point, &MineRange, Option<&Trapped>)>::query().filter(component::<Player>());
+    let mut webs = <(Entity, &Point)>::query().filter(component::<Web>());
     let mouse_pos = Point::from_tuple(input.mouse_pos);
 
     if input.left_click {
@@ -61,8 +241,12 @@ pub fn input(
         lock.0 = !lock.0;
 
         if !lock.0 {
-            player.iter(ecs).for_each(|(entity, pos, mine_range)| {
-                if map.can_mine(mouse_pos) && in_range(*pos, mouse_pos, mine_range.0) {
+            let web_targeted = webs.iter(ecs).any(|(_, pos)| *pos == mouse_pos);
+
+            player.iter(ecs).for_each(|(entity, pos, mine_range, _)| {
+                if (map.can_mine(mouse_pos) || web_targeted)
+                    && in_range(*pos, mouse_pos, mine_range.0)
+                {
                     commands.push((
                         WantsToMine {
                             entity: *entity,
@@ -74,87 +258,358 @@ pub fn input(
                 }
             });
         }
-    } else if input.key != None {
-        let delta = match input.key.unwrap() {
+    } else if let Some(key) = input.key {
+        let delta = match key {
             VirtualKeyCode::A => Point::new(-1, 0),
             VirtualKeyCode::D => Point::new(1, 0),
             VirtualKeyCode::W => Point::new(0, -1),
             VirtualKeyCode::S => Point::new(0, 1),
+            // handled by the action_input system
+            VirtualKeyCode::T
+            | VirtualKeyCode::B
+            | VirtualKeyCode::C
+            | VirtualKeyCode::E
+            | VirtualKeyCode::R
+            | VirtualKeyCode::F => return,
+            _ if menu_index(key).is_some() => return,
             _ => Point::zero(),
         };
 
-        let mut enemies = <(Entity, &Point)>::query().filter(component::<Enemy>());
+        let mut creatures = <(Entity, &Point)>::query().filter(component::<Faction>());
+        let mut friends = <(Entity, &Point, &Name, Option<&Recruitable>)>::query()
+            .filter(component::<Ally>() | component::<Recruitable>());
 
-        player.iter(ecs).for_each(|(player_entity, pos, _)| {
-            let destination = *pos + delta;
-            let mut attacking = false;
+        player
+            .iter(ecs)
+            .for_each(|(player_entity, pos, _, trapped)| {
+                let destination = *pos + delta;
+                let mut attacking = false;
 
-            enemies
-                .iter(ecs)
-                .filter(|(_, pos)| **pos == destination)
-                .for_each(|(enemy_entity, _)| {
+                // attack anything hostile
+                creatures
+                    .iter(ecs)
+                    .filter(|(creature, _)| is_hostile(ecs, *player_entity, **creature))
+                    .filter(|(_, pos)| **pos == destination)
+                    .for_each(|(enemy_entity, _)| {
+                        attacking = true;
+
+                        commands.push((
+                            WantsToAttack {
+                                entity: *player_entity,
+                                target: *enemy_entity,
+                            },
+                            (),
+                        ));
+                    });
+
+                // and cut away webs in the way
+                if webs.iter(ecs).any(|(_, pos)| *pos == destination) {
                     attacking = true;
+                    commands.push((
+                        WantsToMine {
+                            entity: *player_entity,
+                            target: destination,
+                        },
+                        (),
+                    ));
+                }
+
+                let friend = friends
+                    .iter(ecs)
+                    .find(|(_, pos, _, _)| **pos == destination)
+                    .map(|(friend, _, name, recruitable)| {
+                        (*friend, name.0.clone(), recruitable.is_some())
+                    });
+                let mut recruiting = false;
+                if let Some((friend, name, true)) = &friend {
+                    commands.remove_component::<Recruitable>(*friend);
+                    commands.add_component(*friend, Faction::Dwarves);
+                    commands.add_component(
+                        *friend,
+                        Ally {
+                            order: AllyOrder::Follow,
+                        },
+                    );
+                    game_log.log(format!("The {} joins you.", name));
+                    recruiting = true;
+                }
 
+                if let (false, Some(trapped)) = (attacking || recruiting, trapped) {
+                    // struggle against the web instead of moving
+                    if trapped.turns > 1 {
+                        commands.add_component(
+                            *player_entity,
+                            Trapped {
+                                turns: trapped.turns - 1,
+                            },
+                        );
+                    } else {
+                        commands.remove_component::<Trapped>(*player_entity);
+                        webs.iter(ecs)
+                            .filter(|(_, web_pos)| *web_pos == pos)
+                            .for_each(|(web, _)| commands.remove(*web));
+                    }
+                } else if !attacking && !recruiting {
                     commands.push((
-                        WantsToAttack {
+                        WantsToMove {
                             entity: *player_entity,
-                            target: *enemy_entity,
+                            destination,
                         },
                         (),
                     ));
-                });
+                    if let Some((friend, _, _)) = friend {
+                        commands.push((
+                            WantsToMove {
+                                entity: friend,
+                                destination: *pos,
+                            },
+                            (),
+                        ));
+                    }
+                }
+            });
+        *turnstate = TurnState::Player;
+    }
+}
+
+/// Handles player actions other than moving and mining. T places a torch, B builds
+/// a forge, C opens the crafting screen at a forge, E the equipment screen, R
+/// searches for traps, F tells allies to stay or follow and the number keys use the
+/// matching item from the quick-use list.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Torch)]
+#[read_component(Torches)]
+#[read_component(Forge)]
+#[read_component(Name)]
+#[read_component(Consumable)]
+#[read_component(Carried)]
+#[read_component(Trap)]
+#[read_component(Hidden)]
+#[read_component(Ally)]
+pub fn action_input(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] input: &InputValues,
+    #[resource] turnstate: &mut TurnState,
+    #[resource] game_log: &mut GameLog,
+    #[resource] game_stats: &mut GameStats,
+) {
+    let (player_entity, player_pos) = match <(Entity, &Point)>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        Some((entity, pos)) => (*entity, *pos),
+        None => return,
+    };
+    let mut forges = <&Point>::query().filter(component::<Forge>());
+    let at_forge = forges.iter(ecs).any(|pos| in_range(player_pos, *pos, 1));
+
+    match input.key {
+        Some(VirtualKeyCode::T) => {
+            // place a torch underfoot
+            let carried = <&Torches>::query()
+                .filter(component::<Player>())
+                .iter(ecs)
+                .next()
+                .map(|torches| torches.0)
+                .unwrap_or(0);
+            let mut torches = <&Point>::query().filter(component::<Torch>());
 
-            if !attacking {
+            if carried <= 0 {
+                game_log.log("You have no torches left.".to_string());
+            } else if !torches.iter(ecs).any(|pos| *pos == player_pos) {
+                commands.push(torch(player_pos));
+                commands.add_component(player_entity, Torches(carried - 1));
+                game_log.log("Player places a torch.".to_string());
+                *turnstate = TurnState::Player;
+            }
+        }
+        Some(VirtualKeyCode::B) => {
+            if at_forge {
+                game_log.log("There is already a forge here.".to_string());
+            } else if game_stats.gold < FORGE_COST {
+                game_log.log(format!("Building a forge takes {} gold.", FORGE_COST));
+            } else {
+                // build a forge underfoot
+                game_stats.gold -= FORGE_COST;
+                commands.push(forge(player_pos));
+                game_log.log("Player builds a forge.".to_string());
+                *turnstate = TurnState::Player;
+            }
+        }
+        Some(VirtualKeyCode::R) => {
+            // search the surrounding floor for traps
+            let mut found = false;
+            <(Entity, &Point, &Name)>::query()
+                .filter(component::<Trap>() & component::<Hidden>())
+                .iter(ecs)
+                .filter(|(_, pos, _)| in_range(player_pos, **pos, SEARCH_RANGE))
+                .for_each(|(trap, _, name)| {
+                    commands.remove_component::<Hidden>(*trap);
+                    game_log.log(format!("Player finds a {}.", name.0));
+                    found = true;
+                });
+            if !found {
+                game_log.log("Player searches but finds nothing.".to_string());
+            }
+            *turnstate = TurnState::Player;
+        }
+        Some(VirtualKeyCode::F) => {
+            let allies: Vec<(Entity, Ally)> = <(Entity, &Ally)>::query()
+                .iter(ecs)
+                .map(|(entity, ally)| (*entity, *ally))
+                .collect();
+            if allies.is_empty() {
+                game_log.log("You have no allies.".to_string());
+            } else {
+                // any ally following is told to stay, otherwise all follow
+                let order = if allies
+                    .iter()
+                    .any(|(_, ally)| ally.order == AllyOrder::Follow)
+                {
+                    game_log.log("Player tells their allies to stay.".to_string());
+                    AllyOrder::Stay
+                } else {
+                    game_log.log("Player tells their allies to follow.".to_string());
+                    AllyOrder::Follow
+                };
+                allies
+                    .iter()
+                    .for_each(|(entity, _)| commands.add_component(*entity, Ally { order }));
+                *turnstate = TurnState::Player;
+            }
+        }
+        Some(VirtualKeyCode::E) => *turnstate = TurnState::Equipment,
+        Some(VirtualKeyCode::C) => {
+            if at_forge {
+                *turnstate = TurnState::Crafting;
+            } else {
+                game_log.log("You need to stand at a forge to craft.".to_string());
+            }
+        }
+        Some(key) => {
+            let items = quick_use_items(ecs, player_entity);
+            if let Some((_, stack)) = menu_index(key).and_then(|n| items.get(n)) {
                 commands.push((
-                    WantsToMove {
-                        entity: *player_entity,
-                        destination,
+                    WantsToUse {
+                        entity: player_entity,
+                        item: stack[0],
                     },
                     (),
                 ));
+                *turnstate = TurnState::Player;
             }
-        });
-        *turnstate = TurnState::Player;
+        }
+        _ => (),
     }
 }
 
-/// Handles requests given by WantsToMine tag.
+/// Handles requests given by WantsToMine tag. Mined tiles roll their loot table and
+/// publish what was found by the entity that mined them. Health found heals the
+/// miner on the spot.
 #[system]
 #[read_component(WantsToMine)]
 #[read_component(MineRange)]
 #[write_component(Stats)]
 #[read_component(Player)]
+#[read_component(Name)]
+#[read_component(Web)]
+#[read_component(Point)]
 pub fn mining(
     ecs: &mut SubWorld,
     commands: &mut CommandBuffer,
     #[resource] map: &mut Map,
-    #[resource] game_stats: &mut GameStats,
+    #[resource] game_log: &mut GameLog,
+    #[resource] loot_tables: &LootTables,
+    #[resource] rng: &mut RandomNumberGenerator,
 ) {
     let mut entities = <(Entity, &WantsToMine)>::query();
-    let (player_entity, player_stats) = <(Entity, &Stats)>::query()
-        .filter(component::<Player>())
-        .iter(ecs)
-        .find_map(|(entity, stats)| Some((entity, *stats)))
-        .unwrap();
+    let mut webs = <(Entity, &Point)>::query().filter(component::<Web>());
+    let mut trapped = <(Entity, &Point)>::query().filter(component::<Trapped>());
 
     entities.iter(ecs).for_each(|(flag, wants_to_mine)| {
         let idx = map.point2d_to_index(wants_to_mine.target);
-        if map.can_mine(wants_to_mine.target) {
-            match map.tiles[idx] {
-                TileType::Gold => game_stats.gold += 1,
-                TileType::RedCrystal => {
-                    let mut new_stats = player_stats;
-                    if player_stats.health < player_stats.max_health - 3 {
-                        new_stats.health += 3;
-                    } else if player_stats.health < player_stats.max_health {
-                        new_stats.health = player_stats.max_health;
-                    }
-                    commands.add_component(*player_entity, new_stats);
+
+        // clear webs, freeing anything stuck in them
+        webs.iter(ecs)
+            .filter(|(_, pos)| **pos == wants_to_mine.target)
+            .for_each(|(web, _)| {
+                commands.remove(*web);
+                game_log.log("The Web is cut away.".to_string());
+                trapped
+                    .iter(ecs)
+                    .filter(|(_, pos)| **pos == wants_to_mine.target)
+                    .for_each(|(entity, _)| commands.remove_component::<Trapped>(*entity));
+            });
+
+        let (miner_stats, miner_name, is_player) = match ecs.entry_ref(wants_to_mine.entity) {
+            Ok(entry) => (
+                entry.get_component::<Stats>().ok().copied(),
+                entry
+                    .get_component::<Name>()
+                    .map(|name| name.0.clone())
+                    .unwrap_or_default(),
+                entry.get_component::<Player>().is_ok(),
+            ),
+            Err(_) => (None, String::default(), false),
+        };
+
+        let can_mine = if is_player {
+            map.can_mine(wants_to_mine.target)
+        } else {
+            map.can_tunnel(wants_to_mine.target)
+        };
+
+        if can_mine {
+            let tile = map.tiles[idx];
+            let mut healing = 0;
+
+            for (item, quantity) in loot_tables.roll(tile, rng) {
+                if item == LootItem::Health {
+                    healing += quantity;
                 }
-                TileType::GiantGem => game_stats.has_gem = true,
-                _ => (),
+                publish(
+                    commands,
+                    GameEvent::LootFound {
+                        finder: wants_to_mine.entity,
+                        finder_name: miner_name.clone(),
+                        item,
+                        quantity,
+                    },
+                );
             }
-            map.tiles[idx] = TileType::Floor;
+
+            if let Some(stats) = miner_stats {
+                if healing > 0 && stats.health < stats.max_health {
+                    let mut new_stats = stats;
+                    new_stats.health = i32::min(stats.health + healing, stats.max_health);
+                    commands.add_component(wants_to_mine.entity, new_stats);
+                }
+            }
+
+            set_tile(map, commands, wants_to_mine.target, TileType::Floor);
+            publish(
+                commands,
+                GameEvent::TileMined {
+                    miner: Some(wants_to_mine.entity),
+                    position: wants_to_mine.target,
+                    tile,
+                },
+            );
+            commands.push((
+                Noise {
+                    position: wants_to_mine.target,
+                    volume: if is_player {
+                        Noise::MINING
+                    } else {
+                        Noise::TUNNELING
+                    },
+                },
+                (),
+            ));
         }
 
         // remove messsage after processed
@@ -162,345 +617,2319 @@ pub fn mining(
     });
 }
 
-/// Handles requests given by WantsToMove tag.
+/// Number of tiles within a square radius of a point that hold the ceiling up.
+/// Rock and the map edge give support, open tiles do not.
+pub fn ceiling_support(map: &Map, center: Point, radius: i32) -> usize {
+    (-radius..=radius)
+        .flat_map(|y| (-radius..=radius).map(move |x| center + Point::new(x, y)))
+        .filter(|pt| !map.in_bounds(*pt) || !map.can_enter(*pt))
+        .count()
+}
+
+/// Checks if the ceiling around freshly mined tiles is still supported. Tiles with
+/// too little rock around them may start to cave in, giving a turn of falling dust
+/// as warning before the collapse.
 #[system]
-#[read_component(WantsToMove)]
-pub fn movement(ecs: &mut SubWorld, commands: &mut CommandBuffer, #[resource] map: &Map) {
-    let mut entities = <(Entity, &WantsToMove)>::query();
+#[read_component(GameEvent)]
+#[read_component(CaveIn)]
+#[read_component(Point)]
+pub fn structure(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] game_log: &mut GameLog,
+    #[resource] rng: &mut RandomNumberGenerator,
+) {
+    let radius = 2;
+    let min_support = 5;
+    let mut pending: Vec<Point> = <&Point>::query()
+        .filter(component::<CaveIn>())
+        .iter(ecs)
+        .copied()
+        .collect();
 
-    entities.iter(ecs).for_each(|(flag, wants_to_move)| {
-        if map.can_enter(wants_to_move.destination) {
-            commands.add_component(wants_to_move.entity, wants_to_move.destination);
-        }
+    let mined: Vec<Point> = <&GameEvent>::query()
+        .iter(ecs)
+        .filter_map(|event| match event {
+            GameEvent::TileMined { position, .. } => Some(*position),
+            _ => None,
+        })
+        .collect();
 
-        // remove messsage after processed
-        commands.remove(*flag);
+    mined.iter().for_each(|center| {
+        let center = *center;
+        let area: Vec<Point> = (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| center + Point::new(x, y)))
+            .collect();
+
+        let support = ceiling_support(map, center, radius);
+        let already_collapsing = area.iter().any(|pt| pending.contains(pt));
+
+        if support < min_support && !already_collapsing && rng.range(0, 3) == 0 {
+            for pt in area.iter().filter(|pt| map.can_enter(**pt)) {
+                if rng.range(0, 3) > 0 {
+                    commands.push((
+                        CaveIn { turns: 1 },
+                        *pt,
+                        Renderable {
+                            color: ColorPair::new(GRAY, BLACK),
+                            glyph: to_cp437('░'),
+                        },
+                    ));
+                    pending.push(*pt);
+                }
+            }
+            game_log.log("Dust falls from the ceiling...".to_string());
+        }
     });
 }
 
-/// Handles requests given by WantsToAttack tag.
+/// Brings down the ceiling once the dust has settled, burying tiles in rubble and
+/// crushing anything underneath. The Player may be trapped under the rubble.
 #[system]
 #[write_component(Stats)]
-#[read_component(WantsToAttack)]
+#[read_component(CaveIn)]
+#[read_component(Point)]
 #[read_component(Name)]
-pub fn combat(
+#[read_component(Player)]
+#[read_component(Web)]
+pub fn cave_in(
     ecs: &mut SubWorld,
     commands: &mut CommandBuffer,
+    #[resource] map: &mut Map,
     #[resource] game_log: &mut GameLog,
+) {
+    let damage = 5;
+    let mut collapsing = Vec::new();
+
+    <(Entity, &CaveIn, &Point)>::query()
+        .iter(ecs)
+        .for_each(|(entity, cave_in, pos)| {
+            if cave_in.turns > 0 {
+                commands.add_component(
+                    *entity,
+                    CaveIn {
+                        turns: cave_in.turns - 1,
+                    },
+                );
+            } else {
+                collapsing.push(*pos);
+                commands.remove(*entity);
+            }
+        });
+
+    if collapsing.is_empty() {
+        return;
+    }
+    game_log.log("The ceiling collapses!".to_string());
+
+    for pos in collapsing.iter() {
+        if map.can_enter(*pos) {
+            set_tile(map, commands, *pos, TileType::Rubble);
+        }
+    }
+
+    // webs are buried under the rubble
+    <(Entity, &Point)>::query()
+        .filter(component::<Web>())
+        .iter(ecs)
+        .filter(|(_, pos)| collapsing.contains(pos))
+        .for_each(|(web, _)| commands.remove(*web));
+
+    <(Entity, &Point, &mut Stats, &Name, Option<&Player>)>::query()
+        .iter_mut(ecs)
+        .filter(|(_, pos, _, _, _)| collapsing.contains(pos))
+        .for_each(|(entity, _, stats, name, player)| {
+            stats.health -= damage;
+            commands.add_component(
+                *entity,
+                DamagedBy {
+                    entity: None,
+                    name: "Cave-in".to_string(),
+                },
+            );
+            publish(
+                commands,
+                GameEvent::Damaged {
+                    attacker: None,
+                    attacker_name: "Cave-in".to_string(),
+                    cause: DamageCause::CaveIn,
+                    target: *entity,
+                    target_name: name.0.clone(),
+                    amount: damage,
+                },
+            );
+
+            if player.is_some() && stats.health > 0 {
+                commands.add_component(*entity, Trapped { turns: 2 });
+                game_log.log("Player is buried under the rubble!".to_string());
+            }
+        });
+}
+
+/// Handles requests given by WantsToMove tag. Water and rubble slow down whoever
+/// enters them, and ice slides the mover onward until it hits something. Creatures
+/// cannot share a tile, so moves are made in order and a move into a tile taken
+/// earlier in the turn fails. Two creatures moving into each other's tiles swap
+/// places, as long as both of them can make their move.
+#[system]
+#[read_component(WantsToMove)]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Web)]
+#[read_component(WebSpinner)]
+#[read_component(Slowed)]
+pub fn movement(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] occupancy: &mut Occupancy,
     #[resource] game_stats: &mut GameStats,
+    #[resource] game_log: &mut GameLog,
 ) {
-    let mut attackers = <(Entity, &WantsToAttack)>::query();
-    let flags: Vec<(Entity, Entity, Entity)> = attackers
+    let max_slide = 10;
+    let webs: Vec<Point> = <&Point>::query()
+        .filter(component::<Web>())
         .iter(ecs)
-        .map(|(flag, wants_to_attack)| (*flag, wants_to_attack.entity, wants_to_attack.target))
+        .copied()
         .collect();
+    let moves: Vec<(Entity, WantsToMove)> = <(Entity, &WantsToMove)>::query()
+        .iter(ecs)
+        .map(|(flag, wants_to_move)| (*flag, *wants_to_move))
+        .collect();
+    let mover = |entity: Entity| match ecs.entry_ref(entity) {
+        Ok(entry) => (
+            entry.get_component::<Point>().ok().copied(),
+            entry.get_component::<WebSpinner>().is_ok(),
+            entry.get_component::<Player>().is_ok(),
+            entry.get_component::<Slowed>().ok().copied(),
+        ),
+        Err(_) => (None, false, false, None),
+    };
+    // whether terrain and webs let an entity into a tile, leaving aside who stands there
+    let passable = |destination: Point, spinner: bool| {
+        map.can_enter(destination) && (spinner || !webs.contains(&destination))
+    };
 
-    flags.iter().for_each(|(flag, attacker, target)| {
-        let (damage, attacker_name) = if let Ok(v) = ecs.entry_ref(*attacker) {
-            if let (Ok(stats), Ok(name)) = (v.get_component::<Stats>(), v.get_component::<Name>()) {
-                (stats.damage, name.0.clone())
-            } else {
-                (0, String::default())
-            }
-        } else {
-            (0, String::default())
-        };
+    // entities moved as one half of a swap, and every move made
+    let mut swapped: Vec<Entity> = Vec::new();
+    let mut moved: Vec<(Entity, Option<Point>, Point, bool)> = Vec::new();
+
+    for (flag, wants_to_move) in moves.iter() {
+        // remove messsage after processed
+        commands.remove(*flag);
+        let entity = wants_to_move.entity;
+        if swapped.contains(&entity) {
+            continue;
+        }
+
+        let (position, spinner, is_player, slowed) = mover(entity);
 
-        let target_name = if let Ok(v) = ecs.entry_ref(*target) {
-            if let Ok(name) = v.get_component::<Name>() {
-                name.0.clone()
+        // slow terrain eats the move
+        if let Some(Slowed(turns)) = slowed {
+            if turns > 1 {
+                commands.add_component(entity, Slowed(turns - 1));
             } else {
-                String::default()
+                commands.remove_component::<Slowed>(entity);
             }
-        } else {
-            String::default()
-        };
+            if is_player {
+                game_log.log("Player struggles through the terrain.".to_string());
+            }
+            continue;
+        }
 
-        if let Ok(stats) = ecs.entry_mut(*target).unwrap().get_component_mut::<Stats>() {
-            stats.health -= damage;
-            game_log.log(format!(
-                "{} attacked {} for {} dmg.",
-                attacker_name, target_name, damage
-            ));
+        if !passable(wants_to_move.destination, spinner) {
+            continue;
+        }
 
-            // if target dies and is not player, despawn
-            if stats.health <= 0 {
-                if target_name != "Player" {
-                    commands.remove(*target);
-                    game_log.log(format!("{} has been slain.", target_name));
+        let others: Vec<Entity> = occupancy
+            .at(wants_to_move.destination)
+            .iter()
+            .filter(|occupant| **occupant != entity)
+            .copied()
+            .collect();
+        if let [other] = others[..] {
+            // swap places with a creature moving into this one's tile, if it can
+            let swapping = moves.iter().any(|(_, other_move)| {
+                other_move.entity == other && Some(other_move.destination) == position
+            });
+            let (_, other_spinner, other_is_player, other_slowed) = mover(other);
+            if let (true, Some(pos), None) = (swapping, position, other_slowed) {
+                if passable(pos, other_spinner) {
+                    occupancy.relocate(entity, pos, wants_to_move.destination);
+                    occupancy.relocate(other, wants_to_move.destination, pos);
+                    swapped.push(other);
+                    moved.push((entity, position, wants_to_move.destination, is_player));
+                    moved.push((other, Some(wants_to_move.destination), pos, other_is_player));
+                }
+            }
+            continue;
+        } else if !others.is_empty() {
+            continue;
+        }
 
-                    if attacker_name == "Player" {
-                        game_stats.kills += 1;
+        let mut destination = wants_to_move.destination;
+
+        // slide across ice in the direction of travel
+        if let Some(pos) = position {
+            let delta = destination - pos;
+            let mut slid = 0;
+            if delta.x.abs() + delta.y.abs() == 1 {
+                while slid < max_slide
+                    && map.tiles[map.point2d_to_index(destination)] == TileType::Ice
+                {
+                    let next = destination + delta;
+                    if !map.can_enter(next) || occupancy.is_occupied(next, entity) {
+                        break;
                     }
-                } else {
-                    game_stats.slain_by = Some(attacker_name);
+                    destination = next;
+                    slid += 1;
                 }
             }
+            if is_player && slid > 0 {
+                game_log.log("Player slides across the ice.".to_string());
+            }
+            occupancy.relocate(entity, pos, destination);
         }
+        moved.push((entity, position, destination, is_player));
+    }
 
-        // remove messsage after processed
-        commands.remove(*flag);
-    });
-}
+    for (entity, position, destination, is_player) in moved {
+        commands.add_component(entity, destination);
 
-/// Renders map. Sent through a DrawBatch.
-#[system]
-#[write_component(Point)]
-#[read_component(Player)]
-#[read_component(MineRange)]
-pub fn map_render(ecs: &mut SubWorld, #[resource] map: &Map, #[resource] input: &InputValues) {
-    let mouse_pos = Point::from_tuple(input.mouse_pos);
-    let mut draw_batch = DrawBatch::new();
-    let (player_pos, player_range) = <(&Point, &MineRange)>::query()
-        .filter(component::<Player>())
-        .iter(ecs)
-        .find_map(|(pos, range)| Some((*pos, *range)))
+        match map.tiles[map.point2d_to_index(destination)] {
+            TileType::Water => commands.add_component(entity, Slowed(1)),
+            TileType::Rubble => commands.add_component(entity, Slowed(2)),
+            _ => (),
+        }
+
+        if position != Some(destination) {
+            commands.push((
+                Moved {
+                    entity,
+                    position: destination,
+                },
+                (),
+            ));
+        }
+
+        // count steps taken by the player
+        if is_player && position != Some(destination) {
+            game_stats.steps += 1;
+            commands.push((
+                Noise {
+                    position: destination,
+                    volume: Noise::FOOTSTEPS,
+                },
+                (),
+            ));
+        }
+    }
+}
+
+/// Springs traps on anything that stepped onto them this turn. Sprung traps are
+/// revealed, and a rockfall buries its tile in rubble and is used up.
+#[system]
+#[read_component(Moved)]
+#[read_component(Trap)]
+#[read_component(Point)]
+#[read_component(Name)]
+#[read_component(Player)]
+#[write_component(Stats)]
+pub fn traps(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &mut Map,
+    #[resource] light_map: &LightMap,
+    #[resource] game_log: &mut GameLog,
+) {
+    let moves: Vec<(Entity, Entity, Point)> = <(Entity, &Moved)>::query()
+        .iter(ecs)
+        .map(|(flag, moved)| (*flag, moved.entity, moved.position))
+        .collect();
+    let traps: Vec<(Entity, Point, Trap, String)> = <(Entity, &Point, &Trap, &Name)>::query()
+        .iter(ecs)
+        .map(|(entity, pos, trap, name)| (*entity, *pos, *trap, name.0.clone()))
+        .collect();
+
+    for (flag, victim, position) in moves {
+        commands.remove(flag);
+        let (trap_entity, trap, trap_name) =
+            match traps.iter().find(|(_, pos, _, _)| *pos == position) {
+                Some((entity, _, trap, name)) => (*entity, *trap, name.clone()),
+                None => continue,
+            };
+        let (victim_name, is_player) = match ecs.entry_ref(victim) {
+            Ok(entry) => (
+                entry
+                    .get_component::<Name>()
+                    .map(|name| name.0.clone())
+                    .unwrap_or_default(),
+                entry.get_component::<Player>().is_ok(),
+            ),
+            Err(_) => continue,
+        };
+        let seen = is_player || light_map.is_visible(position);
+
+        let damage = match trap.kind {
+            TrapKind::Rockfall => {
+                set_tile(map, commands, position, TileType::Rubble);
+                commands.remove(trap_entity);
+                commands.push((
+                    Noise {
+                        position,
+                        volume: Noise::COMBAT,
+                    },
+                    (),
+                ));
+                if seen {
+                    game_log.log(format!("Rocks crash down on {}!", victim_name));
+                }
+                6
+            }
+            TrapKind::Dart => {
+                commands.remove_component::<Hidden>(trap_entity);
+                if seen {
+                    game_log.log(format!("A dart shoots out at {}!", victim_name));
+                }
+                3
+            }
+            TrapKind::Pit => {
+                commands.remove_component::<Hidden>(trap_entity);
+                commands.add_component(victim, Slowed(2));
+                if seen {
+                    game_log.log(format!("{} falls into a pit!", victim_name));
+                }
+                4
+            }
+        };
+
+        if let Ok(stats) = ecs.entry_mut(victim).unwrap().get_component_mut::<Stats>() {
+            if stats.health <= 0 {
+                continue;
+            }
+            stats.health -= damage;
+            commands.add_component(
+                victim,
+                DamagedBy {
+                    entity: None,
+                    name: trap_name.clone(),
+                },
+            );
+            publish(
+                commands,
+                GameEvent::Damaged {
+                    attacker: None,
+                    attacker_name: trap_name,
+                    cause: DamageCause::Trap,
+                    target: victim,
+                    target_name: victim_name,
+                    amount: damage,
+                },
+            );
+        }
+    }
+}
+
+/// Gives the Player a chance to notice hidden traps next to them each turn.
+#[system]
+#[read_component(Player)]
+#[read_component(Point)]
+#[read_component(Trap)]
+#[read_component(Hidden)]
+#[read_component(Name)]
+pub fn trap_detection(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] rng: &mut RandomNumberGenerator,
+    #[resource] game_log: &mut GameLog,
+) {
+    let player_pos = match <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        Some(pos) => *pos,
+        None => return,
+    };
+
+    <(Entity, &Point, &Name)>::query()
+        .filter(component::<Trap>() & component::<Hidden>())
+        .iter(ecs)
+        .filter(|(_, pos, _)| in_range(player_pos, **pos, 1))
+        .for_each(|(trap, _, name)| {
+            if rng.range(0, 4) == 0 {
+                commands.remove_component::<Hidden>(*trap);
+                game_log.log(format!("Player spots a {}.", name.0));
+            }
+        });
+}
+
+/// Burns everything standing in lava at the end of each round.
+#[system]
+#[write_component(Stats)]
+#[read_component(Point)]
+#[read_component(Name)]
+pub fn hazard(ecs: &mut SubWorld, commands: &mut CommandBuffer, #[resource] map: &Map) {
+    let lava_damage = 4;
+    let mut burning = <(Entity, &Point, &mut Stats, &Name)>::query();
+
+    burning
+        .iter_mut(ecs)
+        .filter(|(_, pos, _, _)| {
+            map.in_bounds(**pos) && map.tiles[map.point2d_to_index(**pos)] == TileType::Lava
+        })
+        .for_each(|(entity, _, stats, name)| {
+            stats.health -= lava_damage;
+            commands.add_component(
+                *entity,
+                DamagedBy {
+                    entity: None,
+                    name: "Lava".to_string(),
+                },
+            );
+            publish(
+                commands,
+                GameEvent::Damaged {
+                    attacker: None,
+                    attacker_name: "Lava".to_string(),
+                    cause: DamageCause::Lava,
+                    target: *entity,
+                    target_name: name.0.clone(),
+                    amount: lava_damage,
+                },
+            );
+        });
+}
+
+/// Handles requests given by WantsToAttack tag. Hits are published as events, and
+/// the attacker is credited should the target die.
+#[system]
+#[write_component(Stats)]
+#[read_component(WantsToAttack)]
+#[read_component(Name)]
+#[read_component(Point)]
+pub fn combat(ecs: &mut SubWorld, commands: &mut CommandBuffer) {
+    let mut attackers = <(Entity, &WantsToAttack)>::query();
+    let flags: Vec<(Entity, Entity, Entity)> = attackers
+        .iter(ecs)
+        .map(|(flag, wants_to_attack)| (*flag, wants_to_attack.entity, wants_to_attack.target))
+        .collect();
+
+    flags.iter().for_each(|(flag, attacker, target)| {
+        // remove messsage after processed
+        commands.remove(*flag);
+
+        // attacks are resolved a turn after they are decided on, by which time
+        // either side may already be gone
+        let (damage, attacker_name) = match ecs.entry_ref(*attacker) {
+            Ok(v) => {
+                if let (Ok(stats), Ok(name)) =
+                    (v.get_component::<Stats>(), v.get_component::<Name>())
+                {
+                    (stats.damage, name.0.clone())
+                } else {
+                    (0, String::default())
+                }
+            }
+            Err(_) => return,
+        };
+        let mut entry = match ecs.entry_mut(*target) {
+            Ok(entry) => entry,
+            Err(_) => return,
+        };
+
+        let target_name = entry
+            .get_component::<Name>()
+            .map(|name| name.0.clone())
+            .unwrap_or_default();
+        let target_pos = entry.get_component::<Point>().ok().copied();
+
+        if let Ok(stats) = entry.get_component_mut::<Stats>() {
+            // armour softens blows, but never stops them entirely
+            let damage = if damage > 0 {
+                i32::max(damage - stats.defence, 1)
+            } else {
+                0
+            };
+            stats.health -= damage;
+            commands.add_component(
+                *target,
+                DamagedBy {
+                    entity: Some(*attacker),
+                    name: attacker_name.clone(),
+                },
+            );
+            publish(
+                commands,
+                GameEvent::Damaged {
+                    attacker: Some(*attacker),
+                    attacker_name: attacker_name.clone(),
+                    cause: DamageCause::Attack,
+                    target: *target,
+                    target_name: target_name.clone(),
+                    amount: damage,
+                },
+            );
+            if let Some(position) = target_pos {
+                commands.push((
+                    Noise {
+                        position,
+                        volume: Noise::COMBAT,
+                    },
+                    (),
+                ));
+            }
+        }
+    });
+}
+
+/// Handles every creature whose health has run out, however it happened. Monsters
+/// leave a corpse and drop loot from their table, and each death is published as
+/// an event crediting whoever dealt the last blow. The Player is left for
+/// cycle_turn to end the run.
+#[system]
+#[read_component(Stats)]
+#[read_component(Name)]
+#[read_component(Point)]
+#[read_component(Renderable)]
+#[read_component(Faction)]
+#[read_component(DamagedBy)]
+#[read_component(Player)]
+#[read_component(LootKey)]
+pub fn deaths(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] monster_loot: &MonsterLoot,
+    #[resource] rng: &mut RandomNumberGenerator,
+) {
+    <(
+        Entity,
+        &Stats,
+        &Name,
+        Option<&DamagedBy>,
+        Option<&Point>,
+        Option<&Renderable>,
+    )>::query()
+    .iter(ecs)
+    .filter(|(_, stats, _, _, _, _)| stats.health <= 0)
+    .for_each(|(entity, _, name, damaged_by, pos, renderable)| {
+        let entry = ecs.entry_ref(*entity).unwrap();
+        publish(
+            commands,
+            GameEvent::Died {
+                entity: *entity,
+                name: name.0.clone(),
+                faction: entry.get_component::<Faction>().ok().copied(),
+                killer: damaged_by.and_then(|damaged_by| damaged_by.entity),
+                killer_name: damaged_by
+                    .map(|damaged_by| damaged_by.name.clone())
+                    .unwrap_or_else(|| "unknown causes".to_string()),
+            },
+        );
+        if entry.get_component::<Player>().is_ok() {
+            return;
+        }
+
+        commands.remove(*entity);
+        if let Some(pos) = pos {
+            if let Some(renderable) = renderable {
+                commands.push(corpse(*pos, &name.0, renderable.color));
+            }
+            let loot = match entry.get_component::<LootKey>() {
+                Ok(key) => monster_loot.roll(&key.0, rng),
+                Err(_) => Vec::new(),
+            };
+            for (item, quantity) in loot {
+                if item == LootItem::Consumable {
+                    for _ in 0..quantity {
+                        commands.push(random_consumable(*pos, rng));
+                    }
+                } else {
+                    commands.push(treasure(*pos, item, quantity));
+                }
+            }
+        }
+    });
+}
+
+/// Moves consumables the Player walks over into their pack, and collects treasure
+/// lying there.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Name)]
+#[read_component(Consumable)]
+#[read_component(Treasure)]
+pub fn pickup(ecs: &SubWorld, commands: &mut CommandBuffer) {
+    let (player, player_pos, player_name) = match <(Entity, &Point, &Name)>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        Some((entity, pos, name)) => (*entity, *pos, name.0.clone()),
+        None => return,
+    };
+
+    <(Entity, &Point, &Name)>::query()
+        .filter(component::<Consumable>())
+        .iter(ecs)
+        .filter(|(_, pos, _)| **pos == player_pos)
+        .for_each(|(item, _, name)| {
+            commands.remove_component::<Point>(*item);
+            commands.add_component(*item, Carried(player));
+            publish(
+                commands,
+                GameEvent::ItemPickedUp {
+                    entity: player,
+                    name: player_name.clone(),
+                    item_name: name.0.clone(),
+                },
+            );
+        });
+
+    <(Entity, &Point, &Treasure)>::query()
+        .iter(ecs)
+        .filter(|(_, pos, _)| **pos == player_pos)
+        .for_each(|(entity, _, treasure)| {
+            commands.remove(*entity);
+            publish(
+                commands,
+                GameEvent::LootFound {
+                    finder: player,
+                    finder_name: player_name.clone(),
+                    item: treasure.item,
+                    quantity: treasure.quantity,
+                },
+            );
+        });
+}
+
+/// Handles requests given by WantsToUse tag. The item takes effect on the entity
+/// using it and is used up.
+#[system]
+#[read_component(WantsToUse)]
+#[read_component(Consumable)]
+#[read_component(Name)]
+#[read_component(Point)]
+#[write_component(Stats)]
+pub fn use_items(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] light_map: &mut LightMap,
+    #[resource] occupancy: &Occupancy,
+    #[resource] rng: &mut RandomNumberGenerator,
+    #[resource] game_log: &mut GameLog,
+) {
+    let requests: Vec<(Entity, Entity, Entity)> = <(Entity, &WantsToUse)>::query()
+        .iter(ecs)
+        .map(|(flag, wants_to_use)| (*flag, wants_to_use.entity, wants_to_use.item))
+        .collect();
+
+    for (flag, user, item) in requests {
+        commands.remove(flag);
+        let (consumable, item_name) = match ecs.entry_ref(item) {
+            Ok(entry) => match (
+                entry.get_component::<Consumable>(),
+                entry.get_component::<Name>(),
+            ) {
+                (Ok(consumable), Ok(name)) => (*consumable, name.0.clone()),
+                _ => continue,
+            },
+            Err(_) => continue,
+        };
+        let user_pos = match ecs
+            .entry_ref(user)
+            .ok()
+            .and_then(|entry| entry.get_component::<Point>().ok().copied())
+        {
+            Some(pos) => pos,
+            None => continue,
+        };
+
+        commands.remove(item);
+        game_log.log(format!("Player uses the {}.", item_name));
+
+        match consumable {
+            Consumable::Healing(amount) => {
+                if let Ok(stats) = ecs.entry_mut(user).unwrap().get_component_mut::<Stats>() {
+                    stats.health = i32::min(stats.health + amount, stats.max_health);
+                }
+                game_log.log("You feel better.".to_string());
+            }
+            Consumable::Mapping(radius) => {
+                for idx in 0..map.tiles.len() {
+                    let pos = map.index_to_point2d(idx);
+                    if DistanceAlg::Pythagoras.distance2d(user_pos, pos) <= radius as f32 {
+                        light_map.revealed[idx] = true;
+                    }
+                }
+                game_log.log("The cave around you is revealed.".to_string());
+            }
+            Consumable::Teleport => {
+                let destinations: Vec<Point> = (0..map.tiles.len())
+                    .map(|idx| map.index_to_point2d(idx))
+                    .filter(|pos| {
+                        map.tiles[map.point2d_to_index(*pos)] == TileType::Floor
+                            && !map.vault.point_in_rect(*pos)
+                            && !occupancy.is_occupied(*pos, user)
+                            && DistanceAlg::Pythagoras.distance2d(user_pos, *pos) > 15.0
+                    })
+                    .collect();
+
+                // the jump is made by the movement system like any other move, so
+                // it springs traps and counts as a step, and nothing slows it down
+                if let Some(idx) = rng.random_slice_index(&destinations) {
+                    commands.remove_component::<Slowed>(user);
+                    commands.push((
+                        WantsToMove {
+                            entity: user,
+                            destination: destinations[idx],
+                        },
+                        (),
+                    ));
+                    game_log.log("The world lurches around you.".to_string());
+                }
+            }
+            Consumable::Strength { damage, turns } => {
+                commands.add_component(user, StrengthBuff { damage, turns });
+                game_log.log("Strength surges through you.".to_string());
+            }
+            Consumable::Bomb => {
+                commands.push(lit_bomb(user_pos));
+                game_log.log("The fuse hisses. Get clear!".to_string());
+            }
+        }
+    }
+}
+
+/// Burns down the fuses of lit explosives at the end of each round. When one runs
+/// out the explosive blows up, mining out the rock around it for loot, hurting
+/// everything in range, burning away webs and setting off any other explosives
+/// caught in the blast.
+#[system]
+#[read_component(Explosive)]
+#[read_component(Fuse)]
+#[read_component(Point)]
+#[read_component(Name)]
+#[read_component(Player)]
+#[read_component(Web)]
+#[write_component(Stats)]
+pub fn explosives(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &mut Map,
+    #[resource] loot_tables: &LootTables,
+    #[resource] rng: &mut RandomNumberGenerator,
+    #[resource] game_log: &mut GameLog,
+) {
+    let explosives: Vec<(Entity, Point, Explosive, String)> =
+        <(Entity, &Point, &Explosive, &Name)>::query()
+            .iter(ecs)
+            .map(|(entity, pos, explosive, name)| (*entity, *pos, *explosive, name.0.clone()))
+            .collect();
+
+    // only the Player sets off explosives, so they collect what the blast uncovers
+    let player = <(Entity, &Name)>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .map(|(entity, name)| (*entity, name.0.clone()));
+
+    let lit_by = player.as_ref().map(|(player, _)| *player);
+    let mut webs: Vec<(Entity, Point)> = <(Entity, &Point)>::query()
+        .filter(component::<Web>())
+        .iter(ecs)
+        .map(|(entity, pos)| (*entity, *pos))
+        .collect();
+
+    let mut blasts: Vec<(Entity, Point, Explosive, String)> = Vec::new();
+    <(Entity, &Fuse)>::query()
+        .filter(component::<Explosive>())
+        .iter(ecs)
+        .for_each(|(entity, fuse)| {
+            if fuse.0 > 1 {
+                commands.add_component(*entity, Fuse(fuse.0 - 1));
+            } else if let Some(explosive) = explosives.iter().find(|(e, _, _, _)| e == entity) {
+                blasts.push(explosive.clone());
+            }
+        });
+
+    // each blast sets off the explosives caught in it
+    let mut n = 0;
+    while n < blasts.len() {
+        let area = blast_area(map, blasts[n].1, blasts[n].2.radius);
+        for explosive in explosives.iter() {
+            if area.contains(&explosive.1) && !blasts.iter().any(|(e, _, _, _)| *e == explosive.0) {
+                blasts.push(explosive.clone());
+            }
+        }
+        n += 1;
+    }
+
+    for (entity, pos, explosive, name) in blasts {
+        commands.remove(entity);
+        game_log.log(format!("The {} explodes!", name));
+        commands.push((
+            Noise {
+                position: pos,
+                volume: Noise::EXPLOSION,
+            },
+            (),
+        ));
+
+        let area = blast_area(map, pos, explosive.radius);
+        webs.retain(|(web, web_pos)| {
+            if area.contains(web_pos) {
+                commands.remove(*web);
+            }
+            !area.contains(web_pos)
+        });
+        for pt in area.iter() {
+            if !map.can_tunnel(*pt) {
+                continue;
+            }
+
+            let idx = map.point2d_to_index(*pt);
+            let tile = map.tiles[idx];
+            if let Some((player, player_name)) = &player {
+                for (item, quantity) in loot_tables.roll(tile, rng) {
+                    if item != LootItem::Health {
+                        publish(
+                            commands,
+                            GameEvent::LootFound {
+                                finder: *player,
+                                finder_name: player_name.clone(),
+                                item,
+                                quantity,
+                            },
+                        );
+                    }
+                }
+            }
+            set_tile(map, commands, *pt, TileType::Floor);
+            publish(
+                commands,
+                GameEvent::TileMined {
+                    miner: lit_by,
+                    position: *pt,
+                    tile,
+                },
+            );
+        }
+
+        <(Entity, &Point, &Name, &mut Stats)>::query().for_each_mut(
+            ecs,
+            |(target, target_pos, target_name, stats)| {
+                if stats.health <= 0 || !area.contains(target_pos) {
+                    return;
+                }
+
+                stats.health -= explosive.damage;
+                commands.add_component(
+                    *target,
+                    DamagedBy {
+                        entity: lit_by,
+                        name: "Explosion".to_string(),
+                    },
+                );
+                publish(
+                    commands,
+                    GameEvent::Damaged {
+                        attacker: lit_by,
+                        attacker_name: "Explosion".to_string(),
+                        cause: DamageCause::Explosion,
+                        target: *target,
+                        target_name: target_name.0.clone(),
+                        amount: explosive.damage,
+                    },
+                );
+            },
+        );
+    }
+}
+
+/// Counts down temporary buffs at the end of each round, removing them once
+/// they wear off.
+#[system]
+#[read_component(StrengthBuff)]
+pub fn buffs(ecs: &SubWorld, commands: &mut CommandBuffer, #[resource] game_log: &mut GameLog) {
+    <(Entity, &StrengthBuff)>::query()
+        .iter(ecs)
+        .for_each(|(entity, buff)| {
+            if buff.turns > 1 {
+                commands.add_component(
+                    *entity,
+                    StrengthBuff {
+                        turns: buff.turns - 1,
+                        ..*buff
+                    },
+                );
+            } else {
+                commands.remove_component::<StrengthBuff>(*entity);
+                game_log.log("Your strength fades.".to_string());
+            }
+        });
+}
+
+/// Handles input on the crafting screen. Number keys craft the matching recipe,
+/// taking a turn, and Escape or C close the screen. Crafted gear is put on if
+/// nothing is worn in its slot yet.
+#[system]
+#[read_component(Player)]
+#[read_component(Equippable)]
+#[read_component(Equipped)]
+pub fn crafting_input(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] input: &InputValues,
+    #[resource] turnstate: &mut TurnState,
+    #[resource] recipes: &Recipes,
+    #[resource] game_stats: &mut GameStats,
+    #[resource] game_log: &mut GameLog,
+) {
+    let key = match input.key {
+        Some(key) => key,
+        None => return,
+    };
+
+    if key == VirtualKeyCode::Escape || key == VirtualKeyCode::C {
+        *turnstate = TurnState::Input;
+        return;
+    }
+
+    let recipe = match menu_index(key).and_then(|n| recipes.recipes.get(n)) {
+        Some(recipe) => recipe,
+        None => return,
+    };
+    if !game_stats.can_afford(recipe) {
+        game_log.log(format!("You lack the materials for the {}.", recipe.name));
+        return;
+    }
+
+    let player = *<Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .unwrap();
+    let slot_free = !<(&Equippable, &Equipped)>::query()
+        .iter(ecs)
+        .any(|(equippable, equipped)| equipped.0 == player && equippable.slot == recipe.slot);
+
+    game_stats.pay(recipe);
+    let item = commands.push(gear(player, &recipe.name, recipe.slot, recipe.bonus));
+    game_log.log(format!("Player forges the {}.", recipe.name));
+    if slot_free {
+        commands.add_component(item, Equipped(player));
+        game_log.log(format!("Player equips the {}.", recipe.name));
+    }
+    *turnstate = TurnState::Player;
+}
+
+/// Draws the crafting screen over the map, listing each recipe and greying out
+/// those the Player cannot afford. Sent through a DrawBatch.
+#[system]
+pub fn crafting_render(#[resource] recipes: &Recipes, #[resource] game_stats: &GameStats) {
+    let mut draw_batch = DrawBatch::new();
+    let width = 56;
+    let height = recipes.recipes.len() as i32 * 2 + 6;
+    let x = (MAP_WIDTH - width) / 2;
+    let y = (MAP_HEIGHT - height) / 2;
+
+    draw_batch.draw_box(
+        Rect::with_size(x, y, width, height),
+        ColorPair::new(WHITE, BLACK),
+    );
+    draw_batch.print_color(
+        Point::new(x + 2, y + 1),
+        "Forge",
+        ColorPair::new(ORANGE, BLACK),
+    );
+
+    for (n, recipe) in recipes.recipes.iter().enumerate().take(9) {
+        let row = y + 3 + n as i32 * 2;
+        let color = if game_stats.can_afford(recipe) {
+            ColorPair::new(WHITE, BLACK)
+        } else {
+            ColorPair::new(DIM_GRAY, BLACK)
+        };
+        draw_batch.print_color(
+            Point::new(x + 2, row),
+            format!(
+                "{}. {} - {} ({})",
+                n + 1,
+                recipe.name,
+                recipe.slot.name(),
+                recipe.bonus.describe()
+            ),
+            color,
+        );
+        draw_batch.print_color(Point::new(x + 5, row + 1), recipe.costs(), color);
+    }
+
+    draw_batch.print(
+        Point::new(x + 2, y + height - 2),
+        "1-9 to craft, ESC to leave",
+    );
+    draw_batch.submit(9000).expect("Batch error");
+}
+
+/// Derives Stats and MineRange from BaseStats plus the bonuses of equipped gear and
+/// any strength buff. Changing gear never heals, losing max health only caps
+/// health.
+#[system]
+#[read_component(BaseStats)]
+#[read_component(Equipped)]
+#[read_component(StatBonus)]
+#[read_component(StrengthBuff)]
+#[write_component(Stats)]
+#[write_component(MineRange)]
+pub fn derive_stats(ecs: &mut SubWorld) {
+    let bonuses: Vec<(Entity, StatBonus)> = <(&Equipped, &StatBonus)>::query()
+        .iter(ecs)
+        .map(|(equipped, bonus)| (equipped.0, *bonus))
+        .collect();
+
+    <(
+        Entity,
+        &BaseStats,
+        &mut Stats,
+        &mut MineRange,
+        Option<&StrengthBuff>,
+    )>::query()
+    .for_each_mut(ecs, |(entity, base, stats, mine_range, buff)| {
+        let gear = bonuses
+            .iter()
+            .filter(|(owner, _)| owner == entity)
+            .fold(StatBonus::default(), |total, (_, bonus)| total + *bonus);
+
+        let max_health = base.max_health + gear.max_health;
+        stats.max_health = max_health;
+        stats.health = i32::min(stats.health, max_health);
+        stats.damage = base.damage + gear.damage + buff.map(|buff| buff.damage).unwrap_or(0);
+        stats.defence = base.defence + gear.defence;
+        mine_range.0 = base.mine_range + gear.mine_range;
+    });
+}
+
+/// Gear carried by the Player that is not being worn, in the order it is listed
+/// on the equipment screen.
+fn spare_gear(ecs: &SubWorld, player: Entity) -> Vec<(Entity, String, Equippable, StatBonus)> {
+    <(Entity, &Name, &Equippable, &StatBonus, &Carried)>::query()
+        .filter(!component::<Equipped>())
+        .iter(ecs)
+        .filter(|(_, _, _, _, carried)| carried.0 == player)
+        .map(|(entity, name, equippable, bonus, _)| (*entity, name.0.clone(), *equippable, *bonus))
+        .collect()
+}
+
+/// Handles input on the equipment screen. Number keys put on the matching spare
+/// gear, swapping out whatever was worn in its slot and taking a turn. Escape or E
+/// close the screen.
+#[system]
+#[read_component(Player)]
+#[read_component(Name)]
+#[read_component(Equippable)]
+#[read_component(Equipped)]
+#[read_component(StatBonus)]
+#[read_component(Carried)]
+pub fn equipment_input(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] input: &InputValues,
+    #[resource] turnstate: &mut TurnState,
+    #[resource] game_log: &mut GameLog,
+) {
+    let key = match input.key {
+        Some(key) => key,
+        None => return,
+    };
+
+    if key == VirtualKeyCode::Escape || key == VirtualKeyCode::E {
+        *turnstate = TurnState::Input;
+        return;
+    }
+
+    let player = *<Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .unwrap();
+    let spare = spare_gear(ecs, player);
+    let (item, name, equippable, _) = match menu_index(key).and_then(|n| spare.get(n)) {
+        Some(gear) => gear,
+        None => return,
+    };
+
+    <(Entity, &Equippable, &Equipped)>::query()
+        .iter(ecs)
+        .filter(|(_, worn, equipped)| equipped.0 == player && worn.slot == equippable.slot)
+        .for_each(|(worn, _, _)| commands.remove_component::<Equipped>(*worn));
+    commands.add_component(*item, Equipped(player));
+    game_log.log(format!("Player equips the {}.", name));
+    *turnstate = TurnState::Player;
+}
+
+/// Draws the equipment screen over the map, listing what is worn in each slot
+/// and the spare gear that can be put on. Sent through a DrawBatch.
+#[system]
+#[read_component(Player)]
+#[read_component(Name)]
+#[read_component(Equippable)]
+#[read_component(Equipped)]
+#[read_component(StatBonus)]
+#[read_component(Carried)]
+pub fn equipment_render(ecs: &SubWorld) {
+    let mut draw_batch = DrawBatch::new();
+    let player = *<Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .unwrap();
+    let spare = spare_gear(ecs, player);
+    let width = 56;
+    let height = spare.len().min(9) as i32 + EquipmentSlot::ALL.len() as i32 + 9;
+    let x = (MAP_WIDTH - width) / 2;
+    let y = (MAP_HEIGHT - height) / 2;
+
+    draw_batch.draw_box(
+        Rect::with_size(x, y, width, height),
+        ColorPair::new(WHITE, BLACK),
+    );
+    draw_batch.print_color(
+        Point::new(x + 2, y + 1),
+        "Equipment",
+        ColorPair::new(ORANGE, BLACK),
+    );
+
+    for (n, slot) in EquipmentSlot::ALL.iter().enumerate() {
+        let worn = <(&Name, &Equippable, &StatBonus, &Equipped)>::query()
+            .iter(ecs)
+            .find(|(_, equippable, _, equipped)| equipped.0 == player && equippable.slot == *slot)
+            .map(|(name, _, bonus, _)| format!("{} ({})", name.0, bonus.describe()))
+            .unwrap_or_else(|| "-".to_string());
+        draw_batch.print(
+            Point::new(x + 2, y + 3 + n as i32),
+            format!("{:<8} {}", slot.name(), worn),
+        );
+    }
+
+    let spare_y = y + 4 + EquipmentSlot::ALL.len() as i32;
+    draw_batch.print_color(
+        Point::new(x + 2, spare_y),
+        "Spare gear",
+        ColorPair::new(ORANGE, BLACK),
+    );
+    if spare.is_empty() {
+        draw_batch.print(Point::new(x + 2, spare_y + 1), "None");
+    }
+    for (n, (_, name, equippable, bonus)) in spare.iter().enumerate().take(9) {
+        draw_batch.print(
+            Point::new(x + 2, spare_y + 1 + n as i32),
+            format!(
+                "{}. {} - {} ({})",
+                n + 1,
+                name,
+                equippable.slot.name(),
+                bonus.describe()
+            ),
+        );
+    }
+
+    draw_batch.print(
+        Point::new(x + 2, y + height - 2),
+        "1-9 to equip, ESC to leave",
+    );
+    draw_batch.submit(9000).expect("Batch error");
+}
+
+/// Renders map. Tiles are tinted by the light falling on them, and tiles out of
+/// sight are drawn from memory. Sent through a DrawBatch.
+#[system]
+#[write_component(Point)]
+#[read_component(Player)]
+#[read_component(MineRange)]
+pub fn map_render(
+    ecs: &mut SubWorld,
+    #[resource] map: &Map,
+    #[resource] input: &InputValues,
+    #[resource] light_map: &LightMap,
+) {
+    let mouse_pos = Point::from_tuple(input.mouse_pos);
+    let mut draw_batch = DrawBatch::new();
+    let (player_pos, player_range) = <(&Point, &MineRange)>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .map(|(pos, range)| (*pos, *range))
+        .next()
+        .unwrap();
+
+    for y in 0..MAP_HEIGHT {
+        for x in 0..MAP_WIDTH {
+            let idx = map.point2d_to_index(Point::new(x, y));
+            let hovered = map.point2d_to_index(mouse_pos) == idx;
+            let (color, glyph) = match map.tiles[idx] {
+                TileType::Floor => (ColorPair::new(WHITE, BLACK), '.'),
+                TileType::Wall => {
+                    let color = {
+                        if hovered {
+                            if in_range(player_pos, mouse_pos, player_range.0) {
+                                ColorPair::new(CYAN, BLACK)
+                            } else {
+                                ColorPair::new(DARK_CYAN, BLACK)
+                            }
+                        } else {
+                            ColorPair::new(BURLYWOOD, BLACK)
+                        }
+                    };
+
+                    (color, '#')
+                }
+                TileType::Gold => {
+                    let color = {
+                        if hovered {
+                            ColorPair::new(GOLDENROD, BLACK)
+                        } else {
+                            ColorPair::new(GOLD, BLACK)
+                        }
+                    };
+
+                    (color, '#')
+                }
+                TileType::RedCrystal => {
+                    let color = {
+                        if hovered {
+                            ColorPair::new(DARK_RED, BLACK)
+                        } else {
+                            ColorPair::new(RED, BLACK)
+                        }
+                    };
+
+                    (color, '#')
+                }
+                TileType::GiantGem => {
+                    let color = {
+                        if hovered {
+                            ColorPair::new(DARK_VIOLET, BLACK)
+                        } else {
+                            ColorPair::new(VIOLET, BLACK)
+                        }
+                    };
+
+                    (color, '#')
+                }
+                TileType::VaultWall => (ColorPair::new(SLATE_BLUE, BLACK), '#'),
+                TileType::VaultDoor => (ColorPair::new(ORANGE, BLACK), '+'),
+                TileType::Water => (ColorPair::new(DODGER_BLUE, BLACK), '~'),
+                TileType::Lava => (ColorPair::new(ORANGE_RED, BLACK), '~'),
+                TileType::Ice => (ColorPair::new(LIGHT_CYAN, BLACK), '='),
+                TileType::Rubble => {
+                    let color = {
+                        if hovered {
+                            ColorPair::new(DARK_GRAY, BLACK)
+                        } else {
+                            ColorPair::new(GRAY, BLACK)
+                        }
+                    };
+
+                    (color, ':')
+                }
+                TileType::Bedrock => (ColorPair::new(DIM_GRAY, BLACK), '#'),
+            };
+
+            if let Some(color) = light_map.shade(Point::new(x, y), color) {
+                draw_batch.set(Point::new(x, y), color, to_cp437(glyph));
+            }
+        }
+    }
+
+    draw_batch.submit(0).expect("Batch error");
+}
+
+/// Marks the tiles lit explosives will blow up once their fuse burns down, where
+/// the Player can see or remembers them. Sent through a DrawBatch.
+#[system]
+#[read_component(Point)]
+#[read_component(Explosive)]
+#[read_component(Fuse)]
+pub fn blast_render(ecs: &SubWorld, #[resource] map: &Map, #[resource] light_map: &LightMap) {
+    let mut draw_batch = DrawBatch::new();
+    <(&Point, &Explosive)>::query()
+        .filter(component::<Fuse>())
+        .iter(ecs)
+        .for_each(|(pos, explosive)| {
+            blast_area(map, *pos, explosive.radius)
+                .into_iter()
+                .filter(|pt| light_map.is_revealed(*pt))
+                .for_each(|pt| {
+                    draw_batch.set_bg(pt, DARK_RED);
+                });
+        });
+    draw_batch.submit(5000).expect("Batch error");
+}
+
+/// Diplays all Renderable entities the Player can see, tinted by the light they
+/// stand in. Sent through a DrawBatch.
+#[system]
+#[read_component(Point)]
+#[read_component(Renderable)]
+#[read_component(Web)]
+#[read_component(CaveIn)]
+#[read_component(Item)]
+#[read_component(Explosive)]
+#[read_component(Trap)]
+#[read_component(Hidden)]
+pub fn entity_render(ecs: &SubWorld, #[resource] light_map: &LightMap) {
+    let mut draw_batch = DrawBatch::new();
+    let mut draw = |position: &Point, renderable: &Renderable| {
+        if light_map.is_visible(*position) {
+            if let Some(color) = light_map.shade(*position, renderable.color) {
+                draw_batch.set(*position, color, renderable.glyph);
+            }
+        }
+    };
+
+    // webs, dust, items, corpses, explosives and traps go underneath anything standing on
+    // them, and hidden traps are not drawn at all
+    <(&Point, &Renderable)>::query()
+        .filter(
+            (component::<Web>()
+                | component::<CaveIn>()
+                | component::<Item>()
+                | component::<Corpse>()
+                | component::<Explosive>()
+                | component::<Trap>())
+                & !component::<Hidden>(),
+        )
+        .iter(ecs)
+        .for_each(|(position, renderable)| draw(position, renderable));
+    <(&Point, &Renderable)>::query()
+        .filter(
+            !component::<Web>()
+                & !component::<CaveIn>()
+                & !component::<Item>()
+                & !component::<Corpse>()
+                & !component::<Explosive>()
+                & !component::<Trap>(),
+        )
+        .iter(ecs)
+        .for_each(|(position, renderable)| draw(position, renderable));
+    draw_batch.submit(8000).expect("Batch error");
+}
+
+/// Rebuilds the LightMap from glowing tiles and light sources, then works out
+/// which tiles the Player can see. Runs once per turn, the glow of tiles is only
+/// worked out again after a glowing tile changes.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(LightSource)]
+pub fn lighting(ecs: &SubWorld, #[resource] map: &Map, #[resource] light_map: &mut LightMap) {
+    light_map.reset_to_glow(map);
+    light_map
+        .visible
+        .iter_mut()
+        .for_each(|visible| *visible = false);
+
+    <(&Point, &LightSource)>::query()
+        .iter(ecs)
+        .for_each(|(pos, light)| light_map.add_light(map, *pos, light.radius));
+
+    if let Some(player_pos) = <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        for pt in field_of_view_set(*player_pos, VIEW_RANGE, map) {
+            if map.in_bounds(pt) {
+                let idx = map.point2d_to_index(pt);
+                if light_map.light[idx] > 0.0 {
+                    light_map.visible[idx] = true;
+                    light_map.revealed[idx] = true;
+                }
+            }
+        }
+    }
+}
+
+/// Handles TurnState switching.
+#[system]
+#[read_component(Player)]
+#[read_component(Stats)]
+pub fn cycle_turn(
+    ecs: &mut SubWorld,
+    #[resource] turnstate: &mut TurnState,
+    #[resource] game_stats: &GameStats,
+) {
+    let current_state = turnstate.clone();
+    let mut new_state = match current_state {
+        TurnState::Player => TurnState::Enemy,
+        TurnState::Enemy => TurnState::Input,
+        _ => current_state,
+    };
+
+    // check game over
+    <&Stats>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .for_each(|stats| {
+            if stats.health <= 0 {
+                new_state = TurnState::GameOver;
+            }
+        });
+
+    // check victory
+    if game_stats.has_gem {
+        new_state = TurnState::Victory;
+    }
+
+    *turnstate = new_state;
+}
+
+/// Rebuilds the Occupancy index from where every creature stands.
+#[system]
+#[read_component(Point)]
+#[read_component(Stats)]
+pub fn occupancy(ecs: &SubWorld, #[resource] occupancy: &mut Occupancy) {
+    occupancy.clear();
+    <(Entity, &Point)>::query()
+        .filter(component::<Stats>())
+        .iter(ecs)
+        .for_each(|(entity, pos)| occupancy.add(*pos, *entity));
+}
+
+/// Brings the FlowFields up to date with where the Player stands and where the
+/// webs are, rebuilding only the fields that are out of date.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Web)]
+pub fn flow_fields(
+    ecs: &SubWorld,
+    #[resource] map: &Map,
+    #[resource] flow_fields: &mut FlowFields,
+) {
+    let player_pos = match <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        Some(pos) => *pos,
+        None => return,
+    };
+    let mut webs: Vec<Point> = <&Point>::query()
+        .filter(component::<Web>())
+        .iter(ecs)
+        .copied()
+        .collect();
+    webs.sort_by_key(|pos| (pos.y, pos.x));
+
+    flow_fields.update(map, player_pos, &webs);
+}
+
+/// Moves an entity to the destination if no other creature stands there,
+/// otherwise attacks whoever there is hostile to it.
+pub fn move_or_attack(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    occupancy: &Occupancy,
+    entity: Entity,
+    destination: Point,
+) {
+    let mut collided = false;
+
+    for target in occupancy.at(destination).iter().filter(|e| **e != entity) {
+        collided = true;
+        // if collided target is hostile then attack
+        if is_hostile(ecs, entity, *target) {
+            commands.push((
+                WantsToAttack {
+                    entity,
+                    target: *target,
+                },
+                (),
+            ));
+        }
+    }
+
+    if !collided {
+        commands.push((
+            WantsToMove {
+                entity,
+                destination,
+            },
+            (),
+        ));
+    }
+}
+
+/// Randomly moves entities with the RandomMovement tag.
+#[system]
+#[read_component(Point)]
+#[read_component(Renderable)]
+#[read_component(RandomMovement)]
+#[read_component(Faction)]
+#[read_component(Player)]
+pub fn random_movement(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] rng: &mut RandomNumberGenerator,
+    #[resource] occupancy: &Occupancy,
+) {
+    <(Entity, &Point)>::query()
+        .filter(component::<Renderable>() & component::<RandomMovement>())
+        .iter(ecs)
+        .for_each(|(entity, pos)| {
+            let destination = *pos + random_delta(rng);
+            move_or_attack(ecs, commands, occupancy, *entity, destination);
+        });
+}
+
+/// Spreads noises through the cave, growing fainter with distance. Monsters that
+/// hear a noise and are not already after the Player go to investigate where it
+/// came from, following the loudest noise they hear.
+#[system]
+#[read_component(Noise)]
+#[read_component(Point)]
+#[read_component(Name)]
+#[write_component(Awareness)]
+pub fn hearing(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] light_map: &LightMap,
+    #[resource] game_log: &mut GameLog,
+) {
+    let search_turns = 10;
+    let noises: Vec<(Noise, DijkstraMap)> = <(Entity, &Noise)>::query()
+        .iter(ecs)
+        .map(|(flag, noise)| {
+            // remove messsage after processed
+            commands.remove(*flag);
+
+            let noise_map = DijkstraMap::new(
+                MAP_WIDTH,
+                MAP_HEIGHT,
+                &[map.point2d_to_index(noise.position)],
+                map,
+                noise.volume as f32,
+            );
+            (*noise, noise_map)
+        })
+        .collect();
+
+    if noises.is_empty() {
+        return;
+    }
+
+    <(&Point, &Name, &mut Awareness)>::query().for_each_mut(ecs, |(pos, name, awareness)| {
+        if !matches!(
+            awareness.state,
+            AwarenessState::Idle | AwarenessState::Searching
+        ) {
+            return;
+        }
+
+        let idx = map.point2d_to_index(*pos);
+        let loudest = noises
+            .iter()
+            .map(|(noise, noise_map)| (noise.position, noise.volume as f32 - noise_map.map[idx]))
+            .filter(|(_, heard)| *heard > 0.0)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((position, _)) = loudest {
+            if awareness.state == AwarenessState::Idle && light_map.is_visible(*pos) {
+                game_log.log(format!("{} hears something.", name.0));
+            }
+            awareness.state = AwarenessState::Searching;
+            awareness.last_known = Some(position);
+            awareness.search_turns = search_turns;
+        }
+    });
+}
+
+/// Updates what monsters with Awareness know about the Player.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Name)]
+#[write_component(Awareness)]
+pub fn perception(ecs: &mut SubWorld, #[resource] map: &Map, #[resource] game_log: &mut GameLog) {
+    let player_pos = *<&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .last()
         .unwrap();
 
-    for y in 0..MAP_HEIGHT {
-        for x in 0..MAP_WIDTH {
-            let idx = map.point2d_to_index(Point::new(x, y));
-            match map.tiles[idx] {
-                TileType::Floor => draw_batch.set(
-                    Point::from_tuple((x, y)),
-                    ColorPair::new(WHITE, BLACK),
-                    to_cp437('.'),
-                ),
-                TileType::Wall => {
-                    let color = {
-                        if map.point2d_to_index(mouse_pos) == idx {
-                            if in_range(player_pos, mouse_pos, player_range.0) {
-                                ColorPair::new(CYAN, BLACK)
-                            } else {
-                                ColorPair::new(DARK_CYAN, BLACK)
-                            }
+    <(&Point, &Name, &mut Awareness)>::query().for_each_mut(ecs, |(pos, name, awareness)| {
+        if can_see(map, *pos, player_pos, awareness.sight_range) {
+            awareness.last_known = Some(player_pos);
+            awareness.state = match awareness.state {
+                AwarenessState::Idle | AwarenessState::Searching => {
+                    game_log.log(format!("{} spots you!", name.0));
+                    AwarenessState::Alerted
+                }
+                _ => AwarenessState::Hunting,
+            };
+        } else {
+            awareness.state = match awareness.state {
+                AwarenessState::Alerted | AwarenessState::Hunting => {
+                    awareness.search_turns = 10;
+                    AwarenessState::Searching
+                }
+                AwarenessState::Searching => {
+                    awareness.search_turns -= 1;
+                    if awareness.search_turns <= 0 || awareness.last_known == Some(*pos) {
+                        awareness.last_known = None;
+                        AwarenessState::Idle
+                    } else {
+                        AwarenessState::Searching
+                    }
+                }
+                AwarenessState::Idle => AwarenessState::Idle,
+            };
+        }
+    });
+}
+
+/// Moves entities with TargetedMovement based on their awareness of the Player.
+/// Idle monsters wander, hunting monsters close in on the Player, searching monsters
+/// head to where the Player was last seen and hurt monsters flee.
+#[system]
+#[read_component(Point)]
+#[read_component(Renderable)]
+#[read_component(Stats)]
+#[read_component(Player)]
+#[read_component(TargetedMovement)]
+#[read_component(Awareness)]
+#[read_component(FleeThreshold)]
+#[read_component(LightAffinity)]
+#[read_component(Faction)]
+pub fn targeted_movement(
+    ecs: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] rng: &mut RandomNumberGenerator,
+    #[resource] light_map: &LightMap,
+    #[resource] occupancy: &Occupancy,
+    #[resource] flow_fields: &FlowFields,
+) {
+    let mut entities = <(
+        Entity,
+        &Point,
+        &Stats,
+        Option<&Awareness>,
+        Option<&FleeThreshold>,
+        Option<&LightAffinity>,
+    )>::query()
+    .filter(component::<Renderable>() & component::<TargetedMovement>());
+    let player_pos = <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .last()
+        .unwrap();
+
+    entities.iter(ecs).for_each(
+        |(entity, pos, stats, awareness, flee_threshold, affinity)| {
+            let entity_idx = map.point2d_to_index(*pos);
+
+            // monsters without perception always know where the player is
+            let awareness = awareness.copied().unwrap_or(Awareness {
+                state: AwarenessState::Hunting,
+                sight_range: 0,
+                last_known: Some(*player_pos),
+                search_turns: 0,
+            });
+            let fleeing = awareness.state != AwarenessState::Idle
+                && flee_threshold
+                    .map(|threshold| stats.health * 100 < stats.max_health * threshold.0)
+                    .unwrap_or(false);
+
+            let destination = if fleeing {
+                DijkstraMap::find_lowest_exit(&flow_fields.flee, entity_idx, map)
+                    .map(|idx| map.index_to_point2d(idx))
+            } else {
+                match awareness.state {
+                    AwarenessState::Idle => {
+                        // wander, drifting towards light if drawn to it
+                        let drawn = affinity
+                            .filter(|_| rng.range(0, 2) == 0)
+                            .and_then(|affinity| light_step(map, light_map, *pos, *affinity));
+                        Some(drawn.unwrap_or_else(|| *pos + random_delta(rng)))
+                    }
+                    AwarenessState::Alerted => None,
+                    AwarenessState::Hunting => {
+                        DijkstraMap::find_lowest_exit(&flow_fields.towards_player, entity_idx, map)
+                            .map(|destination| {
+                                let distance =
+                                    DistanceAlg::Pythagoras.distance2d(*pos, *player_pos);
+                                if distance > 1.2 {
+                                    map.index_to_point2d(destination)
+                                } else {
+                                    *player_pos
+                                }
+                            })
+                    }
+                    AwarenessState::Searching => awareness.last_known.and_then(|target| {
+                        let path = a_star_search(entity_idx, map.point2d_to_index(target), map);
+                        if path.success && path.steps.len() > 1 {
+                            Some(map.index_to_point2d(path.steps[1]))
                         } else {
-                            ColorPair::new(BURLYWOOD, BLACK)
+                            None
                         }
-                    };
+                    }),
+                }
+            };
+
+            if let Some(destination) = destination {
+                move_or_attack(ecs, commands, occupancy, *entity, destination);
+            }
+        },
+    );
+}
+
+/// Moves allies. Allies attack hostiles next to them, and while following close
+/// in on hostiles they can see or otherwise keep near the Player. Allies told to
+/// stay hold their ground.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Faction)]
+#[read_component(Stats)]
+#[read_component(Ally)]
+pub fn ally_movement(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] occupancy: &Occupancy,
+) {
+    let sight_range = 6;
+    let player_pos = match <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        Some(pos) => *pos,
+        None => return,
+    };
+    let creatures: Vec<(Entity, Point)> = <(Entity, &Point)>::query()
+        .filter(component::<Faction>() & component::<Stats>())
+        .iter(ecs)
+        .map(|(entity, pos)| (*entity, *pos))
+        .collect();
+
+    <(Entity, &Point, &Ally)>::query()
+        .iter(ecs)
+        .for_each(|(entity, pos, ally)| {
+            let view = field_of_view_set(*pos, sight_range, map);
+            let nearest = creatures
+                .iter()
+                .filter(|(target, target_pos)| {
+                    view.contains(target_pos) && is_hostile(ecs, *entity, *target)
+                })
+                .min_by(|(_, a), (_, b)| {
+                    DistanceAlg::Pythagoras
+                        .distance2d(*pos, *a)
+                        .total_cmp(&DistanceAlg::Pythagoras.distance2d(*pos, *b))
+                });
 
-                    draw_batch.set(Point::new(x, y), color, to_cp437('#'))
+            if let Some((target, target_pos)) = nearest {
+                if DistanceAlg::Pythagoras.distance2d(*pos, *target_pos) < 1.2 {
+                    commands.push((
+                        WantsToAttack {
+                            entity: *entity,
+                            target: *target,
+                        },
+                        (),
+                    ));
+                    return;
                 }
-                TileType::Gold => {
-                    let color = {
-                        if map.point2d_to_index(mouse_pos) == idx {
-                            ColorPair::new(GOLDENROD, BLACK)
-                        } else {
-                            ColorPair::new(GOLD, BLACK)
-                        }
-                    };
+            }
+            if ally.order == AllyOrder::Stay {
+                return;
+            }
 
-                    draw_batch.set(Point::new(x, y), color, to_cp437('#'))
+            let goal = match nearest {
+                Some((_, target_pos)) => *target_pos,
+                None if DistanceAlg::Pythagoras.distance2d(*pos, player_pos) > 2.0 => player_pos,
+                None => return,
+            };
+            let path = a_star_search(map.point2d_to_index(*pos), map.point2d_to_index(goal), map);
+            if path.success && path.steps.len() > 1 {
+                let destination = map.index_to_point2d(path.steps[1]);
+                if !occupancy.is_occupied(destination, *entity) {
+                    commands.push((
+                        WantsToMove {
+                            entity: *entity,
+                            destination,
+                        },
+                        (),
+                    ));
                 }
-                TileType::RedCrystal => {
-                    let color = {
-                        if map.point2d_to_index(mouse_pos) == idx {
-                            ColorPair::new(DARK_RED, BLACK)
+            }
+        });
+}
+
+/// Moves entities with the Tunneling tag towards a nearby Player, digging
+/// through any rock in the way.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Tunneling)]
+#[read_component(Faction)]
+pub fn tunneling_movement(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] flow_fields: &FlowFields,
+    #[resource] occupancy: &Occupancy,
+) {
+    let sense_range = 15.0;
+    let mut entities = <(Entity, &Point)>::query().filter(component::<Tunneling>());
+    let player_pos = <&Point>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .copied()
+        .last()
+        .unwrap();
+
+    // path through rock as well as open floor
+    let tunnel_map = TunnelMap(map);
+
+    entities.iter(ecs).for_each(|(entity, pos)| {
+        // dormant until the player comes close
+        if DistanceAlg::Pythagoras.distance2d(*pos, player_pos) > sense_range {
+            return;
+        }
+
+        let entity_idx = map.point2d_to_index(*pos);
+        if let Some(idx) =
+            DijkstraMap::find_lowest_exit(&flow_fields.tunnel, entity_idx, &tunnel_map)
+        {
+            let destination = map.index_to_point2d(idx);
+
+            if map.can_enter(destination) {
+                move_or_attack(ecs, commands, occupancy, *entity, destination);
+            } else {
+                commands.push((
+                    WantsToMine {
+                        entity: *entity,
+                        target: destination,
+                    },
+                    (),
+                ));
+            }
+        }
+    });
+}
+
+/// Monsters in a Swarm that spot the Player call the rest of the swarm within range.
+#[system]
+#[read_component(Point)]
+#[read_component(Swarm)]
+#[write_component(Awareness)]
+pub fn swarm(ecs: &mut SubWorld) {
+    let callers: Vec<(Point, i32, Point)> = <(&Point, &Swarm, &Awareness)>::query()
+        .iter(ecs)
+        .filter(|(_, _, awareness)| {
+            matches!(
+                awareness.state,
+                AwarenessState::Alerted | AwarenessState::Hunting
+            )
+        })
+        .filter_map(|(pos, swarm, awareness)| {
+            awareness
+                .last_known
+                .map(|last_known| (*pos, swarm.0, last_known))
+        })
+        .collect();
+
+    <(&Point, &mut Awareness)>::query()
+        .filter(component::<Swarm>())
+        .for_each_mut(ecs, |(pos, awareness)| {
+            if !matches!(
+                awareness.state,
+                AwarenessState::Idle | AwarenessState::Searching
+            ) {
+                return;
+            }
+
+            if let Some((_, _, target)) = callers.iter().find(|(caller, range, _)| {
+                DistanceAlg::Pythagoras.distance2d(*caller, *pos) <= *range as f32
+            }) {
+                awareness.state = AwarenessState::Hunting;
+                awareness.last_known = Some(*target);
+            }
+        });
+}
+
+/// Moves entities with the WebSpinner tag. Spiders lurk in and spin webs while idle,
+/// close in on the Player once they know where they are, wrap them in webs and
+/// retreat into webbed areas when hurt.
+#[system]
+#[read_component(Point)]
+#[read_component(Player)]
+#[read_component(Stats)]
+#[read_component(Name)]
+#[read_component(WebSpinner)]
+#[read_component(Awareness)]
+#[read_component(Web)]
+#[read_component(Trapped)]
+#[read_component(LightAffinity)]
+#[read_component(Faction)]
+pub fn spider_movement(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] rng: &mut RandomNumberGenerator,
+    #[resource] light_map: &LightMap,
+    #[resource] flow_fields: &FlowFields,
+    #[resource] occupancy: &Occupancy,
+) {
+    let max_webs = 60;
+    let bright = 0.4;
+    let mut spiders = <(
+        Entity,
+        &Point,
+        &Stats,
+        &Name,
+        &Awareness,
+        Option<&LightAffinity>,
+    )>::query()
+    .filter(component::<WebSpinner>());
+    let (player_entity, player_pos, player_name, player_trapped) =
+        <(Entity, &Point, &Name, Option<&Trapped>)>::query()
+            .filter(component::<Player>())
+            .iter(ecs)
+            .map(|(entity, pos, name, trapped)| (*entity, *pos, name.0.clone(), trapped.is_some()))
+            .last()
+            .unwrap();
+    let mut web_positions: Vec<Point> = <&Point>::query()
+        .filter(component::<Web>())
+        .iter(ecs)
+        .copied()
+        .collect();
+
+    spiders
+        .iter(ecs)
+        .for_each(|(entity, pos, stats, name, awareness, affinity)| {
+            let entity_idx = map.point2d_to_index(*pos);
+            let on_web = web_positions.contains(pos);
+            let hurt = stats.health < stats.max_health;
+
+            let destination = match awareness.state {
+                AwarenessState::Alerted => None,
+                AwarenessState::Hunting if !hurt => {
+                    if DistanceAlg::Pythagoras.distance2d(*pos, player_pos) < 1.2 {
+                        // wrap the player in a web instead of biting
+                        if !player_trapped
+                            && !web_positions.contains(&player_pos)
+                            && rng.range(0, 3) == 0
+                        {
+                            commands.push(web(player_pos));
+                            commands.add_component(player_entity, Trapped { turns: 3 });
+                            web_positions.push(player_pos);
+                            publish(
+                                commands,
+                                GameEvent::Webbed {
+                                    entity: player_entity,
+                                    name: player_name.clone(),
+                                    spinner_name: name.0.clone(),
+                                },
+                            );
+                            None
                         } else {
-                            ColorPair::new(RED, BLACK)
+                            Some(player_pos)
                         }
-                    };
-
-                    draw_batch.set(Point::new(x, y), color, to_cp437('#'))
+                    } else {
+                        DijkstraMap::find_lowest_exit(&flow_fields.towards_player, entity_idx, map)
+                            .map(|idx| map.index_to_point2d(idx))
+                    }
                 }
-                TileType::GiantGem => {
-                    let color = {
-                        if map.point2d_to_index(mouse_pos) == idx {
-                            ColorPair::new(DARK_VIOLET, BLACK)
+                AwarenessState::Searching if !hurt => awareness.last_known.and_then(|target| {
+                    let path = a_star_search(entity_idx, map.point2d_to_index(target), map);
+                    if path.success && path.steps.len() > 1 {
+                        Some(map.index_to_point2d(path.steps[1]))
+                    } else {
+                        None
+                    }
+                }),
+                _ => {
+                    if !on_web && !web_positions.is_empty() && (hurt || rng.range(0, 2) == 0) {
+                        // head back to the webs
+                        DijkstraMap::find_lowest_exit(&flow_fields.interest, entity_idx, map)
+                            .map(|idx| map.index_to_point2d(idx))
+                    } else {
+                        // lurk, mostly staying within the webs
+                        let destination = *pos + random_delta(rng);
+                        if !on_web || web_positions.contains(&destination) || rng.range(0, 4) == 0 {
+                            Some(destination)
                         } else {
-                            ColorPair::new(VIOLET, BLACK)
+                            None
                         }
-                    };
+                    }
+                }
+            };
 
-                    draw_batch.set(Point::new(x, y), color, to_cp437('#'))
+            // keep to the dark unless closing in for the kill
+            let hunting = awareness.state == AwarenessState::Hunting && !hurt;
+            let destination = match affinity {
+                Some(LightAffinity::Avoids) if !hunting => {
+                    let too_bright = |pt: Point| light_map.level(pt) > bright;
+                    if too_bright(*pos) {
+                        light_step(map, light_map, *pos, LightAffinity::Avoids)
+                    } else {
+                        destination.filter(|pt| !too_bright(*pt))
+                    }
                 }
+                _ => destination,
             };
-        }
-    }
 
-    draw_batch.submit(0).expect("Batch error");
-}
+            // spin webs while idle
+            if awareness.state == AwarenessState::Idle
+                && !on_web
+                && web_positions.len() < max_webs
+                && rng.range(0, 8) == 0
+            {
+                commands.push(web(*pos));
+                web_positions.push(*pos);
+            }
 
-/// Diplays all Renderable entities. Sent through a DrawBatch.
-#[system]
-#[read_component(Point)]
-#[read_component(Renderable)]
-pub fn entity_render(ecs: &SubWorld) {
-    let mut draw_batch = DrawBatch::new();
-    <(&Point, &Renderable)>::query()
-        .iter(ecs)
-        .for_each(|(position, renderable)| {
-            draw_batch.set(*position, renderable.color, renderable.glyph);
+            if let Some(destination) = destination {
+                move_or_attack(ecs, commands, occupancy, *entity, destination);
+            }
         });
-    draw_batch.submit(8000).expect("Batch error");
 }
 
-/// Handles TurnState switching.
+/// Handles the phases and attacks of Boss monsters. Bosses guard their home and
+/// fight in melee at first, summon goblins below two thirds health and become
+/// enraged below one third, charging and slamming the ground around them.
 #[system]
+#[read_component(Point)]
 #[read_component(Player)]
 #[read_component(Stats)]
-pub fn cycle_turn(
-    ecs: &mut SubWorld,
-    #[resource] turnstate: &mut TurnState,
-    #[resource] game_stats: &GameStats,
+#[read_component(Name)]
+#[read_component(Awareness)]
+#[read_component(Boss)]
+#[read_component(Web)]
+#[read_component(Trap)]
+#[read_component(Faction)]
+pub fn boss(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &Map,
+    #[resource] game_log: &mut GameLog,
+    #[resource] occupancy: &Occupancy,
+    #[resource] flow_fields: &FlowFields,
 ) {
-    let current_state = turnstate.clone();
-    let mut new_state = match current_state {
-        TurnState::Player => TurnState::Enemy,
-        TurnState::Enemy => TurnState::Input,
-        _ => current_state,
-    };
-
-    // check game over
-    <&Stats>::query()
+    let leash = 10.0;
+    let player_pos = <&Point>::query()
         .filter(component::<Player>())
         .iter(ecs)
-        .for_each(|stats| {
-            if stats.health <= 0 {
-                new_state = TurnState::GameOver;
-            }
-        });
+        .copied()
+        .last()
+        .unwrap();
+    let bosses: Vec<(Entity, Point, Stats, String, Awareness, Boss)> =
+        <(Entity, &Point, &Stats, &Name, &Awareness, &Boss)>::query()
+            .iter(ecs)
+            .map(|(entity, pos, stats, name, awareness, boss)| {
+                (*entity, *pos, *stats, name.0.clone(), *awareness, *boss)
+            })
+            .collect();
+    let occupied = |pt: Point| !occupancy.at(pt).is_empty();
+    let snares: Vec<Point> = <&Point>::query()
+        .filter(component::<Web>() | component::<Trap>())
+        .iter(ecs)
+        .copied()
+        .collect();
 
-    // check victory
-    if game_stats.has_gem {
-        new_state = TurnState::Victory;
+    if bosses.is_empty() {
+        return;
     }
+    let player_map = &flow_fields.towards_player;
 
-    *turnstate = new_state;
-}
-
-/// Randomly moves entities with the RandomMovement tag.
-#[system]
-#[read_component(Point)]
-#[read_component(Renderable)]
-#[read_component(RandomMovement)]
-#[read_component(Stats)]
-#[read_component(Player)]
-pub fn random_movement(
-    ecs: &mut SubWorld,
-    commands: &mut CommandBuffer,
-    #[resource] rng: &mut RandomNumberGenerator,
-) {
-    let mut entities = <(Entity, &Point)>::query()
-        .filter(component::<Renderable>() & component::<RandomMovement>());
-    let mut positions = <(Entity, &Point)>::query().filter(component::<Stats>());
-    let mut collided = false;
+    for (entity, pos, stats, name, awareness, boss) in bosses {
+        let mut boss = boss;
+        boss.turns += 1;
 
-    entities.iter(ecs).for_each(|(entity, pos)| {
-        let delta = match rng.range(0, 4) {
-            0 => Point::new(-1, 0),
-            1 => Point::new(1, 0),
-            2 => Point::new(0, 1),
-            _ => Point::new(0, -1),
+        // change phase as health drops
+        let phase = if stats.health * 3 > stats.max_health * 2 {
+            1
+        } else if stats.health * 3 > stats.max_health {
+            2
+        } else {
+            3
         };
-        let destination = *pos + delta;
+        if phase > boss.phase {
+            if phase == 2 {
+                game_log.log(format!("{} bellows for his minions!", name));
+            } else {
+                game_log.log(format!("{} flies into a rage!", name));
+                let mut new_stats = stats;
+                new_stats.damage += 3;
+                commands.add_component(entity, new_stats);
+            }
+            boss.phase = phase;
+        }
 
-        positions
-            .iter(ecs)
-            .filter(|(_, target_pos)| **target_pos == destination)
-            .for_each(|(target, _)| {
-                collided = true;
-                // if collided target is a player then attack
-                if ecs
-                    .entry_ref(*target)
-                    .unwrap()
-                    .get_component::<Player>()
-                    .is_ok()
-                {
+        let distance = DistanceAlg::Pythagoras.distance2d(pos, player_pos);
+        let engaged = awareness.state != AwarenessState::Idle
+            && DistanceAlg::Pythagoras.distance2d(boss.home, player_pos) <= leash;
+
+        // summon goblins onto free tiles around the boss
+        if engaged && boss.phase >= 2 && boss.turns % 5 == 0 {
+            let mut summoned = 0;
+            for delta in [
+                Point::new(-1, -1),
+                Point::new(1, -1),
+                Point::new(-1, 1),
+                Point::new(1, 1),
+            ] {
+                let spawn = pos + delta;
+                if summoned < 2 && map.can_enter(spawn) && !occupied(spawn) {
+                    let minion = commands.push(goblin(spawn));
+                    commands.add_component(
+                        minion,
+                        Awareness {
+                            state: AwarenessState::Hunting,
+                            sight_range: 8,
+                            last_known: Some(player_pos),
+                            search_turns: 0,
+                        },
+                    );
+                    summoned += 1;
+                }
+            }
+            if summoned > 0 {
+                game_log.log(format!("{} summons {} goblins!", name, summoned));
+            }
+        }
+
+        if !engaged {
+            // return to guard the vault
+            let path = a_star_search(
+                map.point2d_to_index(pos),
+                map.point2d_to_index(boss.home),
+                map,
+            );
+            if path.success && path.steps.len() > 1 {
+                let destination = map.index_to_point2d(path.steps[1]);
+                if !occupied(destination) {
+                    commands.push((
+                        WantsToMove {
+                            entity,
+                            destination,
+                        },
+                        (),
+                    ));
+                }
+            }
+        } else if boss.phase == 3 && distance < 2.9 && boss.turns % 3 == 0 {
+            // ground slam hits every foe around the boss
+            game_log.log(format!("{} slams the ground!", name));
+            (-2..=2)
+                .flat_map(|dy| (-2..=2).map(move |dx| pos + Point::new(dx, dy)))
+                .flat_map(|pt| occupancy.at(pt).iter())
+                .filter(|target| is_hostile(ecs, entity, **target))
+                .for_each(|target| {
                     commands.push((
                         WantsToAttack {
-                            entity: *entity,
+                            entity,
                             target: *target,
                         },
                         (),
                     ));
+                });
+        } else if distance < 1.2 {
+            move_or_attack(ecs, commands, occupancy, entity, player_pos);
+        } else if let Some(idx) =
+            DijkstraMap::find_lowest_exit(player_map, map.point2d_to_index(pos), map)
+        {
+            // enraged bosses charge two tiles at a time over open floor, but stop
+            // at anything standing, spun or set in the way
+            let mut destination = map.index_to_point2d(idx);
+            if boss.phase == 3
+                && map.tiles[idx] == TileType::Floor
+                && !occupied(destination)
+                && !snares.contains(&destination)
+            {
+                if let Some(idx) = DijkstraMap::find_lowest_exit(player_map, idx, map) {
+                    let next = map.index_to_point2d(idx);
+                    if next != player_pos && !occupied(next) {
+                        destination = next;
+                    }
                 }
-            });
+            }
 
-        if !collided {
-            commands.push((
-                WantsToMove {
-                    entity: *entity,
-                    destination,
-                },
-                (),
-            ));
+            move_or_attack(ecs, commands, occupancy, entity, destination);
         }
-    });
+
+        commands.add_component(entity, boss);
+    }
 }
 
-/// Moves entities with TargetedMovement towards Player.
+/// Opens the vault doors once no Boss is left guarding them.
 #[system]
-#[read_component(Point)]
-#[read_component(Renderable)]
-#[read_component(Stats)]
-#[read_component(Player)]
-#[read_component(TargetedMovement)]
-pub fn targeted_movement(ecs: &mut SubWorld, commands: &mut CommandBuffer, #[resource] map: &Map) {
-    let mut entities = <(Entity, &Point)>::query()
-        .filter(component::<Renderable>() & component::<TargetedMovement>());
-    let mut positions = <(Entity, &Point)>::query().filter(component::<Stats>());
-    let player_pos = <&Point>::query()
-        .filter(component::<Player>())
-        .iter(ecs)
-        .last()
-        .unwrap();
-    let player_idx = map.point2d_to_index(*player_pos);
-    let mut collided = false;
-
-    // create the dijkstra map to the player
-    let dijkstra_map = DijkstraMap::new(MAP_WIDTH, MAP_HEIGHT, &[player_idx], map, 1024.0);
-
-    // move each entity to the player
-    entities.iter(ecs).for_each(|(entity, pos)| {
-        let entity_idx = map.point2d_to_index(*pos);
-
-        if let Some(destination) = DijkstraMap::find_lowest_exit(&dijkstra_map, entity_idx, map) {
-            let distance = DistanceAlg::Pythagoras.distance2d(*pos, *player_pos);
-            let destination = if distance > 1.2 {
-                map.index_to_point2d(destination)
-            } else {
-                *player_pos
-            };
-
-            positions
-                .iter(ecs)
-                .filter(|(_, target_pos)| **target_pos == destination)
-                .for_each(|(target, _)| {
-                    collided = true;
-                    // if collided target is a player then attack
-                    if ecs
-                        .entry_ref(*target)
-                        .unwrap()
-                        .get_component::<Player>()
-                        .is_ok()
-                    {
-                        commands.push((
-                            WantsToAttack {
-                                entity: *entity,
-                                target: *target,
-                            },
-                            (),
-                        ));
-                    }
-                });
-
-            if !collided {
-                commands.push((
-                    WantsToMove {
-                        entity: *entity,
-                        destination,
-                    },
-                    (),
-                ));
-            }
+#[read_component(Boss)]
+pub fn vault(
+    ecs: &SubWorld,
+    commands: &mut CommandBuffer,
+    #[resource] map: &mut Map,
+    #[resource] game_log: &mut GameLog,
+) {
+    if !map.vault_doors.is_empty() && <&Boss>::query().iter(ecs).next().is_none() {
+        for door in std::mem::take(&mut map.vault_doors) {
+            set_tile(map, commands, door, TileType::Floor);
         }
-    });
+        game_log.log("The vault door grinds open!".to_string());
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use legion::{storage::Component, systems::Builder};
+
+    /// Builds a World with the resources most systems expect around `map`.
+    fn test_world(map: Map) -> (World, Resources) {
+        let mut resources = Resources::default();
+        resources.insert(map);
+        resources.insert(GameLog::new());
+        resources.insert(GameStats::default());
+        resources.insert(RandomNumberGenerator::seeded(1));
+        resources.insert(LightMap::new());
+        resources.insert(LootTables::default());
+        resources.insert(Occupancy::new());
+        resources.insert(FlowFields::new());
+        (World::default(), resources)
+    }
+
+    /// Removes every message of type `T` from the world, returning them.
+    fn take_messages<T: Component + Copy>(ecs: &mut World) -> Vec<T> {
+        let messages: Vec<(Entity, T)> = <(Entity, &T)>::query()
+            .iter(ecs)
+            .map(|(flag, message)| (*flag, *message))
+            .collect();
+        messages
+            .into_iter()
+            .map(|(flag, message)| {
+                ecs.remove(flag);
+                message
+            })
+            .collect()
+    }
+
+    /// Runs the systems added by `build` once over the world.
+    fn run(
+        ecs: &mut World,
+        resources: &mut Resources,
+        build: impl FnOnce(&mut Builder) -> &mut Builder,
+    ) {
+        build(&mut Schedule::builder())
+            .build()
+            .execute(ecs, resources);
+    }
 
     #[test]
     fn in_range_test() {
@@ -509,4 +2938,593 @@ mod test {
         let range = 1;
         assert!(in_range(p1, p2, range));
     }
+
+    #[test]
+    fn derive_stats_test() {
+        let (mut ecs, mut resources) = test_world(Map::blank());
+        let player = ecs.push((
+            BaseStats {
+                max_health: 20,
+                damage: 5,
+                defence: 0,
+                mine_range: 1,
+            },
+            Stats {
+                max_health: 20,
+                health: 18,
+                damage: 5,
+                defence: 0,
+            },
+            MineRange(1),
+        ));
+        let plate = ecs.push((
+            Equipped(player),
+            StatBonus {
+                max_health: 5,
+                defence: 1,
+                ..StatBonus::default()
+            },
+        ));
+        let stats = |ecs: &World| {
+            *ecs.entry_ref(player)
+                .unwrap()
+                .get_component::<Stats>()
+                .unwrap()
+        };
+
+        // putting gear on raises max health without healing
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(derive_stats_system())
+        });
+        assert_eq!(stats(&ecs).max_health, 25);
+        assert_eq!(stats(&ecs).health, 18);
+        assert_eq!(stats(&ecs).defence, 1);
+
+        // and swapping it off and on again never heals
+        ecs.entry(plate).unwrap().remove_component::<Equipped>();
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(derive_stats_system())
+        });
+        ecs.entry(plate).unwrap().add_component(Equipped(player));
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(derive_stats_system())
+        });
+        assert_eq!(stats(&ecs).health, 18);
+
+        // losing max health caps health
+        ecs.entry(player)
+            .unwrap()
+            .get_component_mut::<Stats>()
+            .unwrap()
+            .health = 24;
+        ecs.entry(plate).unwrap().remove_component::<Equipped>();
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(derive_stats_system())
+        });
+        assert_eq!(stats(&ecs).max_health, 20);
+        assert_eq!(stats(&ecs).health, 20);
+    }
+
+    #[test]
+    fn can_see_test() {
+        let mut map = Map::blank();
+        let pos = Point::new(10, 10);
+
+        assert!(can_see(&map, pos, Point::new(14, 10), 8));
+        assert!(!can_see(&map, pos, Point::new(20, 10), 8));
+
+        // wall blocks line of sight
+        let idx = map.point2d_to_index(Point::new(12, 10));
+        map.tiles[idx] = TileType::Wall;
+        assert!(!can_see(&map, pos, Point::new(14, 10), 8));
+    }
+
+    #[test]
+    fn tunneling_test() {
+        let mut map = Map::blank();
+        map.tiles.iter_mut().for_each(|tile| *tile = TileType::Wall);
+        for x in [10, 14] {
+            let idx = map.point2d_to_index(Point::new(x, 10));
+            map.tiles[idx] = TileType::Floor;
+        }
+        let ore = Point::new(11, 10);
+        let ore_idx = map.point2d_to_index(ore);
+        map.tiles[ore_idx] = TileType::Gold;
+        let (mut ecs, mut resources) = test_world(map);
+        resources.insert(LootTables::from_text("[Gold]\ngold 1 2 2").unwrap());
+
+        ecs.push((Player, Name("Player".to_string()), Point::new(14, 10)));
+        let worm = ecs.push((Name("Rock Worm".to_string()), Point::new(10, 10), Tunneling));
+
+        // the worm digs straight through the rock towards the Player
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(flow_fields_system())
+                .add_system(tunneling_movement_system())
+        });
+        let digs: Vec<WantsToMine> = <&WantsToMine>::query().iter(&ecs).copied().collect();
+        assert_eq!(
+            digs,
+            vec![WantsToMine {
+                entity: worm,
+                target: ore,
+            }]
+        );
+
+        // and crushes the ore it digs through instead of handing it to the Player
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(mining_system())
+                .flush()
+                .add_system(log_events_system())
+                .add_system(record_events_system())
+        });
+        assert_eq!(
+            resources.get::<Map>().unwrap().tiles[ore_idx],
+            TileType::Floor
+        );
+        assert_eq!(resources.get::<GameStats>().unwrap().gold, 0);
+        assert_eq!(
+            resources.get::<GameLog>().unwrap().entries,
+            vec!["Rock Worm crushes the Gold."]
+        );
+    }
+
+    #[test]
+    fn cave_in_test() {
+        let mut map = Map::blank();
+        let center = Point::new(10, 10);
+        assert_eq!(ceiling_support(&map, center, 2), 0);
+        // the map edge counts as rock
+        assert_eq!(ceiling_support(&map, Point::zero(), 2), 16);
+        for x in 8..=12 {
+            let idx = map.point2d_to_index(Point::new(x, 8));
+            map.tiles[idx] = TileType::Wall;
+        }
+        assert_eq!(ceiling_support(&map, center, 2), 5);
+        assert_eq!(ceiling_support(&map, center, 1), 0);
+
+        // once the dust settles the ceiling buries whatever is underneath
+        let (mut ecs, mut resources) = test_world(map);
+        let player = ecs.push((
+            Player,
+            Name("Player".to_string()),
+            center,
+            Stats {
+                max_health: 20,
+                health: 20,
+                damage: 5,
+                defence: 0,
+            },
+        ));
+        ecs.push(web(center));
+        ecs.push((CaveIn { turns: 1 }, center));
+        let idx = resources.get::<Map>().unwrap().point2d_to_index(center);
+
+        run(&mut ecs, &mut resources, |s| s.add_system(cave_in_system()));
+        assert_eq!(resources.get::<Map>().unwrap().tiles[idx], TileType::Floor);
+
+        run(&mut ecs, &mut resources, |s| s.add_system(cave_in_system()));
+        assert_eq!(resources.get::<Map>().unwrap().tiles[idx], TileType::Rubble);
+        let entry = ecs.entry_ref(player).unwrap();
+        assert_eq!(entry.get_component::<Stats>().unwrap().health, 15);
+        assert!(entry.get_component::<Trapped>().is_ok());
+        assert_eq!(<&Web>::query().iter(&ecs).count(), 0);
+        assert_eq!(<&CaveIn>::query().iter(&ecs).count(), 0);
+    }
+
+    #[test]
+    fn use_items_test() {
+        let mut map = Map::blank();
+        map.tiles.iter_mut().for_each(|tile| *tile = TileType::Wall);
+        // one free floor tile far away, one taken by a goblin and one flooded
+        let free = Point::new(40, 20);
+        for pos in [Point::new(5, 5), free, Point::new(41, 20)] {
+            let idx = map.point2d_to_index(pos);
+            map.tiles[idx] = TileType::Floor;
+        }
+        let lava_idx = map.point2d_to_index(Point::new(42, 20));
+        map.tiles[lava_idx] = TileType::Lava;
+        let (mut ecs, mut resources) = test_world(map);
+
+        let player = ecs.push((
+            Point::new(5, 5),
+            Stats {
+                max_health: 20,
+                health: 15,
+                damage: 5,
+                defence: 0,
+            },
+        ));
+        ecs.push((
+            Point::new(41, 20),
+            Stats {
+                max_health: 15,
+                health: 15,
+                damage: 3,
+                defence: 0,
+            },
+        ));
+        let use_item = |ecs: &mut World, resources: &mut Resources, kind| {
+            let item = ecs.push((Item, Name("Item".to_string()), kind));
+            ecs.push((WantsToUse {
+                entity: player,
+                item,
+            },));
+            run(ecs, resources, |s| {
+                s.add_system(occupancy_system())
+                    .add_system(use_items_system())
+                    .flush()
+                    .add_system(movement_system())
+            });
+            assert!(ecs.entry_ref(item).is_err());
+        };
+
+        // healing never goes past max health
+        use_item(&mut ecs, &mut resources, Consumable::Healing(10));
+        let entry = ecs.entry_ref(player).unwrap();
+        assert_eq!(entry.get_component::<Stats>().unwrap().health, 20);
+
+        // teleporting only lands on free floor, moving like any other step, and
+        // pulls the user out of whatever slowed them
+        ecs.entry(player).unwrap().add_component(Slowed(2));
+        use_item(&mut ecs, &mut resources, Consumable::Teleport);
+        let entry = ecs.entry_ref(player).unwrap();
+        assert_eq!(*entry.get_component::<Point>().unwrap(), free);
+        assert!(entry.get_component::<Slowed>().is_err());
+        let moved: Vec<Moved> = <&Moved>::query().iter(&ecs).copied().collect();
+        assert_eq!(
+            moved,
+            vec![Moved {
+                entity: player,
+                position: free,
+            }]
+        );
+    }
+
+    #[test]
+    fn explosives_test() {
+        let map = Map::blank();
+        assert_eq!(blast_area(&map, Point::new(10, 10), 1).len(), 9);
+        assert_eq!(blast_area(&map, Point::new(10, 10), 2).len(), 21);
+        // the area stops at the map edge
+        assert_eq!(blast_area(&map, Point::zero(), 1).len(), 4);
+
+        let (mut ecs, mut resources) = test_world(map);
+        let bomb = ecs.push(lit_bomb(Point::new(10, 10)));
+        ecs.entry(bomb).unwrap().add_component(Fuse(1));
+        // the first keg is in reach of the bomb, the second only of the first keg
+        let kegs: Vec<Entity> = [12, 15, 25]
+            .iter()
+            .map(|x| ecs.push(powder_keg(Point::new(*x, 10))))
+            .collect();
+        let goblin = ecs.push((
+            Name("Goblin".to_string()),
+            Point::new(11, 10),
+            Stats {
+                max_health: 20,
+                health: 20,
+                damage: 3,
+                defence: 0,
+            },
+        ));
+
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(explosives_system())
+        });
+
+        assert!(ecs.entry_ref(bomb).is_err());
+        assert!(ecs.entry_ref(kegs[0]).is_err());
+        assert!(ecs.entry_ref(kegs[1]).is_err());
+        assert!(ecs.entry_ref(kegs[2]).is_ok());
+        // caught in the bomb and the first keg, but out of reach of the second
+        let entry = ecs.entry_ref(goblin).unwrap();
+        assert_eq!(entry.get_component::<Stats>().unwrap().health, 2);
+    }
+
+    #[test]
+    fn traps_test() {
+        let (mut ecs, mut resources) = test_world(Map::blank());
+        let plate = Point::new(11, 10);
+        let dart = ecs.push(trap(plate, TrapKind::Dart));
+        let goblin = ecs.push((
+            Name("Goblin".to_string()),
+            Point::new(10, 10),
+            Stats {
+                max_health: 15,
+                health: 15,
+                damage: 3,
+                defence: 0,
+            },
+        ));
+        ecs.push((WantsToMove {
+            entity: goblin,
+            destination: plate,
+        },));
+
+        // monsters set traps off just like the Player
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(occupancy_system())
+                .add_system(movement_system())
+                .flush()
+                .add_system(traps_system())
+        });
+
+        let entry = ecs.entry_ref(goblin).unwrap();
+        assert_eq!(*entry.get_component::<Point>().unwrap(), plate);
+        assert_eq!(entry.get_component::<Stats>().unwrap().health, 12);
+        let entry = ecs.entry_ref(dart).unwrap();
+        assert!(entry.get_component::<Hidden>().is_err());
+        assert_eq!(<&Moved>::query().iter(&ecs).count(), 0);
+    }
+
+    #[test]
+    fn ally_movement_test() {
+        let (mut ecs, mut resources) = test_world(Map::blank());
+        let stats = Stats {
+            max_health: 12,
+            health: 12,
+            damage: 3,
+            defence: 0,
+        };
+        ecs.push((Player, Point::new(10, 10), stats, Faction::Dwarves));
+        let ally = ecs.push((
+            Point::new(15, 10),
+            stats,
+            Faction::Dwarves,
+            Ally {
+                order: AllyOrder::Stay,
+            },
+        ));
+        let orders = |ecs: &mut World, resources: &mut Resources| {
+            run(ecs, resources, |s| {
+                s.add_system(occupancy_system())
+                    .add_system(ally_movement_system())
+            });
+            (
+                take_messages::<WantsToMove>(ecs)
+                    .iter()
+                    .map(|wants_to_move| wants_to_move.destination)
+                    .collect::<Vec<Point>>(),
+                take_messages::<WantsToAttack>(ecs)
+                    .iter()
+                    .map(|wants_to_attack| wants_to_attack.target)
+                    .collect::<Vec<Entity>>(),
+            )
+        };
+
+        // an ally told to stay holds its position
+        assert_eq!(orders(&mut ecs, &mut resources), (vec![], vec![]));
+
+        // but still fights anything hostile that comes next to it
+        let goblin = ecs.push((Point::new(16, 10), stats, Faction::Goblins));
+        assert_eq!(orders(&mut ecs, &mut resources), (vec![], vec![goblin]));
+        ecs.remove(goblin);
+
+        // and catches up with the Player once told to follow
+        ecs.entry(ally).unwrap().add_component(Ally {
+            order: AllyOrder::Follow,
+        });
+        assert_eq!(
+            orders(&mut ecs, &mut resources),
+            (vec![Point::new(14, 10)], vec![])
+        );
+    }
+
+    #[test]
+    fn spider_movement_test() {
+        let (mut ecs, mut resources) = test_world(Map::blank());
+        let stats = |health| Stats {
+            max_health: 5,
+            health,
+            damage: 1,
+            defence: 0,
+        };
+        let hunting = Awareness {
+            state: AwarenessState::Hunting,
+            ..Awareness::new(6)
+        };
+        let spider = |position: Point, health| {
+            (
+                Name("Cave Spider".to_string()),
+                position,
+                stats(health),
+                WebSpinner,
+                hunting,
+                Faction::Spiders,
+            )
+        };
+
+        // already stuck in a web, so the Player can only be bitten
+        let player = ecs.push((
+            Player,
+            Name("Player".to_string()),
+            Point::new(10, 10),
+            stats(5),
+            Faction::Dwarves,
+            Trapped { turns: 2 },
+        ));
+        let biter = ecs.push(spider(Point::new(11, 10), 5));
+        // hurt spiders give up the hunt and retreat into their webs
+        let hurt = ecs.push(spider(Point::new(20, 10), 2));
+        ecs.push(web(Point::new(25, 10)));
+
+        run(&mut ecs, &mut resources, |s| {
+            s.add_system(occupancy_system())
+                .add_system(flow_fields_system())
+                .add_system(spider_movement_system())
+        });
+
+        let attacks: Vec<WantsToAttack> = <&WantsToAttack>::query().iter(&ecs).copied().collect();
+        assert_eq!(
+            attacks,
+            vec![WantsToAttack {
+                entity: biter,
+                target: player,
+            }]
+        );
+        let moves: Vec<WantsToMove> = <&WantsToMove>::query().iter(&ecs).copied().collect();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].entity, hurt);
+        assert_eq!(moves[0].destination, Point::new(21, 10));
+    }
+
+    #[test]
+    fn boss_test() {
+        let mut map = Map::blank();
+        let door = Point::new(15, 5);
+        let door_idx = map.point2d_to_index(door);
+        map.tiles[door_idx] = TileType::Wall;
+        map.vault_doors.push(door);
+        let (mut ecs, mut resources) = test_world(map);
+
+        ecs.push((Player, Point::new(10, 10)));
+        spawn_goblin_king(&mut ecs, Point::new(15, 10));
+        let boss = <Entity>::query()
+            .filter(component::<Boss>())
+            .iter(&ecs)
+            .copied()
+            .next()
+            .unwrap();
+        ecs.entry(boss).unwrap().add_component(Awareness {
+            state: AwarenessState::Hunting,
+            ..Awareness::new(10)
+        });
+        let step = |ecs: &mut World, resources: &mut Resources, health| {
+            let mut entry = ecs.entry(boss).unwrap();
+            entry.get_component_mut::<Stats>().unwrap().health = health;
+            run(ecs, resources, |s| {
+                s.add_system(occupancy_system())
+                    .add_system(flow_fields_system())
+                    .add_system(boss_system())
+            });
+            let moves: Vec<Point> = take_messages::<WantsToMove>(ecs)
+                .iter()
+                .map(|wants_to_move| wants_to_move.destination)
+                .collect();
+            let entry = ecs.entry_ref(boss).unwrap();
+            (
+                *entry.get_component::<Boss>().unwrap(),
+                *entry.get_component::<Stats>().unwrap(),
+                moves,
+            )
+        };
+
+        // below two thirds health the boss calls for help, but still walks
+        let (state, _, moves) = step(&mut ecs, &mut resources, 29);
+        assert_eq!(state.phase, 2);
+        assert_eq!(moves, vec![Point::new(14, 10)]);
+
+        // below a third it flies into a rage and charges two tiles at a time
+        let (state, stats, moves) = step(&mut ecs, &mut resources, 15);
+        assert_eq!(state.phase, 3);
+        assert_eq!(stats.damage, 7);
+        assert_eq!(moves, vec![Point::new(13, 10)]);
+
+        // but not over a web or trap
+        let web = ecs.push(web(Point::new(14, 10)));
+        let (_, _, moves) = step(&mut ecs, &mut resources, 15);
+        assert_eq!(moves, vec![Point::new(14, 10)]);
+        ecs.remove(web);
+
+        // nor through a goblin standing in the way
+        ecs.push((
+            Point::new(14, 10),
+            Faction::Goblins,
+            Stats {
+                max_health: 1,
+                health: 1,
+                damage: 1,
+                defence: 0,
+            },
+        ));
+        let (_, _, moves) = step(&mut ecs, &mut resources, 15);
+        assert!(moves.is_empty());
+
+        // the vault stays shut while the boss lives
+        run(&mut ecs, &mut resources, |s| s.add_system(vault_system()));
+        assert_eq!(
+            resources.get::<Map>().unwrap().tiles[door_idx],
+            TileType::Wall
+        );
+        ecs.remove(boss);
+        run(&mut ecs, &mut resources, |s| s.add_system(vault_system()));
+        let map = resources.get::<Map>().unwrap();
+        assert_eq!(map.tiles[door_idx], TileType::Floor);
+        assert!(map.vault_doors.is_empty());
+    }
+
+    #[test]
+    fn noise_test() {
+        let mut map = Map::blank();
+        let rock = Point::new(11, 10);
+        let rock_idx = map.point2d_to_index(rock);
+        map.tiles[rock_idx] = TileType::Wall;
+        let (mut ecs, mut resources) = test_world(map);
+
+        // worms dig more quietly than the Player
+        let worm = ecs.push((Name("Rock Worm".to_string()), Point::new(10, 10)));
+        ecs.push((WantsToMine {
+            entity: worm,
+            target: rock,
+        },));
+        run(&mut ecs, &mut resources, |s| s.add_system(mining_system()));
+        let volumes: Vec<i32> = <&Noise>::query()
+            .iter(&ecs)
+            .map(|noise| noise.volume)
+            .collect();
+        assert_eq!(volumes, vec![Noise::TUNNELING]);
+
+        // a goblin eight tiles away goes to look only when the noise is loud enough
+        let goblin = ecs.push((
+            Name("Goblin".to_string()),
+            Point::new(11, 18),
+            Awareness::new(8),
+        ));
+        let awareness = |ecs: &World| {
+            *ecs.entry_ref(goblin)
+                .unwrap()
+                .get_component::<Awareness>()
+                .unwrap()
+        };
+        run(&mut ecs, &mut resources, |s| s.add_system(hearing_system()));
+        assert_eq!(awareness(&ecs).state, AwarenessState::Idle);
+        assert_eq!(<&Noise>::query().iter(&ecs).count(), 0);
+
+        ecs.push((Noise {
+            position: rock,
+            volume: Noise::MINING,
+        },));
+        run(&mut ecs, &mut resources, |s| s.add_system(hearing_system()));
+        assert_eq!(awareness(&ecs).state, AwarenessState::Searching);
+        assert_eq!(awareness(&ecs).last_known, Some(rock));
+    }
+
+    #[test]
+    fn combat_test() {
+        let (mut ecs, mut resources) = test_world(Map::blank());
+        let stats = Stats {
+            max_health: 15,
+            health: 15,
+            damage: 3,
+            defence: 0,
+        };
+        let goblin = ecs.push((Name("Goblin".to_string()), Point::new(10, 10), stats));
+        let gone = ecs.push((Name("Goblin".to_string()), Point::new(11, 10), stats));
+        ecs.remove(gone);
+
+        // attacks by or on something that died before its turn came are dropped
+        ecs.push((WantsToAttack {
+            entity: goblin,
+            target: gone,
+        },));
+        ecs.push((WantsToAttack {
+            entity: gone,
+            target: goblin,
+        },));
+        run(&mut ecs, &mut resources, |s| s.add_system(combat_system()));
+
+        assert_eq!(<&WantsToAttack>::query().iter(&ecs).count(), 0);
+        assert_eq!(<&GameEvent>::query().iter(&ecs).count(), 0);
+        let entry = ecs.entry_ref(goblin).unwrap();
+        assert_eq!(entry.get_component::<Stats>().unwrap().health, 15);
+    }
 }
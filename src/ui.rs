@@ -23,6 +23,13 @@ impl GameLog {
 #[system]
 #[read_component(Player)]
 #[read_component(Stats)]
+#[read_component(Torches)]
+#[read_component(Name)]
+#[read_component(Equippable)]
+#[read_component(Equipped)]
+#[read_component(Consumable)]
+#[read_component(Carried)]
+#[read_component(Ally)]
 pub fn ui_render(
     ecs: &mut SubWorld,
     #[resource] game_log: &mut GameLog,
@@ -65,7 +72,10 @@ pub fn ui_render(
     );
 
     // stats
-    draw_batch.print(Point::new(ui_x + 2, 6), format!("Damage: {}", stats.damage));
+    draw_batch.print(
+        Point::new(ui_x + 2, 6),
+        format!("Damage: {}  Defence: {}", stats.damage, stats.defence),
+    );
     draw_batch.print(
         Point::new(ui_x + 2, 8),
         format!("Gold: {}", game_stats.gold),
@@ -74,6 +84,83 @@ pub fn ui_render(
         Point::new(ui_x + 2, 10),
         format!("Kills: {}", game_stats.kills),
     );
+    draw_batch.print(
+        Point::new(ui_x + 2, 12),
+        format!("Gems: {}", game_stats.gems),
+    );
+    draw_batch.print(
+        Point::new(ui_x + 2, 14),
+        format!("Crystals: {}", game_stats.crystals),
+    );
+    if let Some(torches) = <&Torches>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+    {
+        draw_batch.print(Point::new(ui_x + 2, 16), format!("Torches: {}", torches.0));
+    }
+
+    // gear summary
+    draw_batch.print(Point::new(ui_x + 2, 18), "Gear:");
+    let mut worn: Vec<(EquipmentSlot, String)> = <(&Name, &Equippable)>::query()
+        .filter(component::<Equipped>())
+        .iter(ecs)
+        .map(|(name, equippable)| (equippable.slot, name.0.clone()))
+        .collect();
+    worn.sort_by_key(|(slot, _)| EquipmentSlot::ALL.iter().position(|s| s == slot));
+    if worn.is_empty() {
+        draw_batch.print(Point::new(ui_x + 4, 19), "None");
+    }
+    for (n, (_, name)) in worn.iter().enumerate() {
+        draw_batch.print(Point::new(ui_x + 4, 19 + n as i32), name);
+    }
+
+    // quick-use list, two rows per stack so long names fit
+    draw_batch.print(Point::new(ui_x + 2, 24), "Items (1-9):");
+    let player = <Entity>::query()
+        .filter(component::<Player>())
+        .iter(ecs)
+        .next()
+        .copied();
+    let items = player
+        .map(|player| quick_use_items(ecs, player))
+        .unwrap_or_default();
+    if items.is_empty() {
+        draw_batch.print(Point::new(ui_x + 4, 25), "None");
+    }
+    for (n, (name, stack)) in items.iter().enumerate().take(9) {
+        let row = 25 + n as i32 * 2;
+        draw_batch.print(Point::new(ui_x + 2, row), format!("{} {}", n + 1, name));
+        draw_batch.print(Point::new(ui_x + 4, row + 1), format!("x{}", stack.len()));
+    }
+
+    // allies and their health
+    let allies: Vec<(String, Stats, AllyOrder)> = <(&Name, &Stats, &Ally)>::query()
+        .iter(ecs)
+        .map(|(name, stats, ally)| (name.0.clone(), *stats, ally.order))
+        .collect();
+    if !allies.is_empty() {
+        let order = if allies
+            .iter()
+            .any(|(_, _, order)| *order == AllyOrder::Follow)
+        {
+            "follow"
+        } else {
+            "stay"
+        };
+        draw_batch.print(Point::new(ui_x + 2, 44), format!("Allies - {}:", order));
+        for (n, (name, stats, _)) in allies.iter().enumerate() {
+            let row = 45 + n as i32 * 2;
+            draw_batch.print(Point::new(ui_x + 4, row), name);
+            draw_batch.bar_horizontal(
+                Point::new(ui_x + 4, row + 1),
+                width - 6,
+                stats.health,
+                stats.max_health,
+                ColorPair::new(RED, BLACK),
+            );
+        }
+    }
 
     // output log messages
     let mut entries = game_log.entries.clone();